@@ -1,48 +1,249 @@
-use defmt::{error, unwrap};
+use core::cell::RefCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+
+use critical_section::{CriticalSection, Mutex};
+use defmt::{assert, error, unwrap, warn};
 use embassy_rp::peripherals::UART0;
-use embassy_rp::uart;
 use embassy_rp::uart::UartTx;
+use embassy_rp::{pac, uart};
+
+const TX_LEN: usize = 1024;
+
+// Kept out of the startup zeroing, so whatever was still queued when a watchdog or software reset
+// hit is sent after the reboot.
+#[unsafe(link_section = ".uninit.TX_BUF")]
+static mut TX_BUF: MaybeUninit<[u8; TX_LEN]> = MaybeUninit::uninit();
+static TX_TAKEN: AtomicBool = AtomicBool::new(false);
+static TX_PRODUCER: Mutex<RefCell<Option<rbq::FrameProducer<'static>>>> =
+    Mutex::new(RefCell::new(None));
+// for the panic handler, which can't reach the consumer owned by `to_serial`
+static TX_RING: Mutex<RefCell<Option<rbq::Ring<'static>>>> = Mutex::new(RefCell::new(None));
+// set once `to_serial` owns a configured uart, along with the dma channel it sends through
+static UART_READY: AtomicBool = AtomicBool::new(false);
+static UART_DMA: AtomicU8 = AtomicU8::new(0);
+// bytes of logs the queue still held from before a warm reset
+static RECOVERED: AtomicUsize = AtomicUsize::new(0);
+// set while `unqueued` logs, so `Logger::release` leaves the frame in `FRAME`
+static UNQUEUED: AtomicBool = AtomicBool::new(false);
+
+// Longest log frame that is staged, as long as the whole queue memory. A frame that doesn't fit in
+// the ring is dropped whole, since the host can't decode a truncated one, but nothing that fits is
+// lost to staging.
+const FRAME_MAX: usize = TX_LEN;
+
+struct Frame {
+    buf: [u8; FRAME_MAX],
+    len: usize,
+    overflow: bool,
+}
+
+static FRAME: Mutex<RefCell<Frame>> = Mutex::new(RefCell::new(Frame {
+    buf: [0; FRAME_MAX],
+    len: 0,
+    overflow: false,
+}));
+
+static mut RESTORE: critical_section::RestoreState = critical_section::RestoreState::invalid();
+
+/// Installs the producer side of the log queue. Anything logged before this is dropped.
+///
+/// Logs left over from before a warm reset come out of the queue first.
+pub fn init() -> rbq::FrameConsumer<'static> {
+    assert!(!TX_TAKEN.swap(true, Ordering::Relaxed));
+
+    // the memory is only ever handed out here, and after a cold boot it holds whatever the ram
+    // powered up with, which the ring doesn't trust
+    let buf = unsafe { &mut *(&raw mut TX_BUF).cast::<[u8; TX_LEN]>() };
+    let (queue, boot) = unwrap!(rbq::Ring::from_persistent(buf));
+    let (producer, consumer) = unwrap!(queue.split_framed());
+    critical_section::with(|cs| {
+        TX_PRODUCER.borrow_ref_mut(cs).replace(producer);
+        TX_RING.borrow_ref_mut(cs).replace(queue);
+    });
 
-static TX_BUF: rbq::Buffer<1024> = rbq::Buffer::new();
-static TX_QUEUE: rbq::Ring<'static> = rbq::Ring::new(&TX_BUF);
+    // announced by `to_serial` in front of them
+    if let rbq::Boot::Warm { pending } = boot {
+        RECOVERED.store(pending, Ordering::Relaxed);
+    }
+
+    consumer
+}
+
+// Encodes the frames `log` logs into `buf` rather than the queue, and returns their length. For
+// messages that have to go out ahead of whatever is queued.
+fn unqueued(buf: &mut [u8], log: impl FnOnce()) -> usize {
+    critical_section::with(|cs| {
+        UNQUEUED.store(true, Ordering::Relaxed);
+        log();
+        UNQUEUED.store(false, Ordering::Relaxed);
+
+        let frame = FRAME.borrow_ref(cs);
+        let len = match frame.overflow {
+            true => 0,
+            false => frame.len.min(buf.len()),
+        };
+        buf[..len].copy_from_slice(&frame.buf[..len]);
+        len
+    })
+}
 
 #[defmt::global_logger]
 struct Logger;
 
+// Every log message is collected into `FRAME` between `acquire` and `release` and then pushed as a
+// single record. When the queue is full the oldest messages make room, so the newest one (usually
+// the error) is what survives. Messages that are lost anyway, because they're too long or the
+// oldest one is being sent right now, are counted in `Dropped` like the ones pushed out.
 unsafe impl defmt::Logger for Logger {
-    fn acquire() {}
+    fn acquire() {
+        let restore = unsafe { critical_section::acquire() };
+        unsafe { RESTORE = restore };
+
+        let cs = unsafe { CriticalSection::new() };
+        let mut frame = FRAME.borrow_ref_mut(cs);
+        frame.len = 0;
+        frame.overflow = false;
+    }
+
     unsafe fn flush() {}
-    unsafe fn release() {}
+
+    unsafe fn release() {
+        let cs = unsafe { CriticalSection::new() };
+
+        {
+            let frame = FRAME.borrow_ref(cs);
+            let producer = TX_PRODUCER.borrow_ref(cs);
+
+            match producer.as_ref() {
+                _ if UNQUEUED.load(Ordering::Relaxed) => {}
+                Some(producer) if frame.overflow => producer.discard(frame.len),
+                Some(producer) => {
+                    if let Ok(mut grant) = producer.grant_overwrite(frame.len) {
+                        grant.buf_mut()[..frame.len].copy_from_slice(&frame.buf[..frame.len]);
+                        grant.commit(frame.len);
+                    }
+                }
+                None => {}
+            }
+        }
+
+        unsafe { critical_section::release(RESTORE) };
+    }
 
     unsafe fn write(buf: &[u8]) {
-        critical_section::with(|cs| {
-            let Ok(mut grant) = TX_QUEUE.grant_exact(cs, buf.len()) else {
-                return;
-            };
+        let cs = unsafe { CriticalSection::new() };
+        let mut frame = FRAME.borrow_ref_mut(cs);
+        let start = frame.len;
 
-            grant.buf_mut().copy_from_slice(buf);
-            grant.commit(cs, buf.len());
-        });
+        match frame.buf.get_mut(start..(start + buf.len())) {
+            Some(dst) => {
+                dst.copy_from_slice(buf);
+                frame.len += buf.len();
+            }
+            None => frame.overflow = true,
+        }
     }
 }
 
+// Sends whatever is still queued, the panic message included, straight through the uart
+// registers. `to_serial` never runs again, and it may have been stopped holding a read grant, so
+// the ring is drained from underneath it.
+fn flush_to_serial() {
+    if !UART_READY.load(Ordering::Acquire) {
+        return;
+    }
+
+    // let the record `to_serial` was sending finish first
+    let dma = pac::DMA.ch(UART_DMA.load(Ordering::Relaxed) as usize);
+    while dma.ctrl_trig().read().busy() {}
+
+    critical_section::with(|cs| {
+        if let Some(ring) = TX_RING.borrow_ref(cs).as_ref() {
+            let send = |record: &[u8]| {
+                for &byte in record {
+                    while pac::UART0.uartfr().read().txff() {}
+                    pac::UART0.uartdr().write(|w| w.set_data(byte));
+                }
+            };
+
+            // nothing else runs anymore, and the consumer's grant is never used again
+            unsafe { ring.drain_framed_unchecked(send) };
+        }
+    });
+}
+
 #[defmt::panic_handler]
 fn defmt_panic() -> ! {
+    flush_to_serial();
     loop {}
 }
 
 #[panic_handler]
 fn core_panic(info: &core::panic::PanicInfo) -> ! {
     error!("core panic: {:?}", info);
+    flush_to_serial();
     loop {}
 }
 
 #[embassy_executor::task]
-pub async fn to_serial(mut tx: UartTx<'static, UART0, uart::Async>) {
+pub async fn to_serial(
+    rx: rbq::FrameConsumer<'static>,
+    mut tx: UartTx<'static, UART0, uart::Async>,
+    // the number of the channel `tx` was created with
+    dma: u8,
+) {
+    UART_DMA.store(dma, Ordering::Relaxed);
+    UART_READY.store(true, Ordering::Release);
+    let mut dropped = rx.dropped();
+    let mut buf = [0; FRAME_MAX];
+
+    // sent straight out, queueing it would put it behind the recovered logs and push the oldest
+    // of them out of a full queue
+    let recovered = RECOVERED.load(Ordering::Relaxed);
+    if recovered > 0 {
+        let len = unqueued(&mut buf, || {
+            warn!(
+                "previous boot: {} bytes of logs below were recovered",
+                recovered
+            )
+        });
+        unwrap!(tx.write(&buf[..len]).await);
+    }
+
     loop {
-        let grant = TX_QUEUE.poll(|q, cs| q.read(cs).ok()).await;
-        let size = grant.buf().len();
-        unwrap!(tx.write(grant.buf()).await);
-        critical_section::with(|cs| grant.commit(cs, size));
+        // copied out so the record is released while the uart is busy, otherwise the logger
+        // can't push it out to make room and drops the newest message instead
+        // a malformed record (only ever left over from before a reset) is dropped and counted by
+        // the ring, and whatever comes after it is tried right away
+        let grant = rx
+            .poll(|q| match q.read() {
+                Err(rbq::Error::InsufficientSize) => None,
+                result => Some(result.ok()),
+            })
+            .await;
+
+        let len = match grant {
+            Some(grant) => {
+                let len = grant.buf().len().min(FRAME_MAX);
+                buf[..len].copy_from_slice(&grant.buf()[..len]);
+                grant.commit();
+                len
+            }
+            None => 0,
+        };
+
+        if len > 0 {
+            unwrap!(tx.write(&buf[..len]).await);
+        }
+
+        let now = rx.dropped();
+        if now != dropped {
+            warn!(
+                "dropped {} log messages",
+                now.records.wrapping_sub(dropped.records)
+            );
+            dropped = now;
+        }
     }
 }
@@ -5,19 +5,24 @@ mod log;
 
 use defmt::{info, unwrap};
 use embassy_executor::Spawner;
+use embassy_rp::dma::Channel;
 use embassy_rp::uart;
 use embassy_rp::uart::UartTx;
 
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
+    let log_rx = log::init();
     info!("starting...");
 
     info!("initializing HAL");
     let p = embassy_rp::init(Default::default());
 
     info!("starting log sink worker over serial on pin 0...");
-    let uart_tx = UartTx::new(p.UART0, p.PIN_0, p.DMA_CH0, uart::Config::default());
-    unwrap!(spawner.spawn(log::to_serial(uart_tx)));
+    // the panic handler waits for this channel to finish before it takes over the uart
+    let dma = p.DMA_CH0;
+    let channel = dma.number();
+    let uart_tx = UartTx::new(p.UART0, p.PIN_0, dma, uart::Config::default());
+    unwrap!(spawner.spawn(log::to_serial(log_rx, uart_tx, channel)));
 
     info!("startup sequence finished");
 }
@@ -0,0 +1,82 @@
+// Blocking variants of the grant methods, for code that runs outside of an executor such as early
+// boot or an interrupt handler. Compiled in with the `time` feature.
+//
+// They spin until the other side makes room or data, or until the deadline passes, and then fail
+// the same way a single attempt would. Only useful when the other side runs in another context,
+// anything it has to wait for in the same one never comes.
+//
+// There's deliberately no WFE variant. WFE only returns on an event, and neither a commit from the
+// other core nor the deadline passing raises one unless every commit sends SEV and a timer alarm
+// is armed for the deadline, which the ring can't do on its own. Without both it could sleep well
+// past the deadline or miss the commit it waits for.
+
+use core::hint;
+
+use embassy_time::Instant;
+
+use crate::buffer::Ring;
+use crate::frame::{FrameConsumer, FrameGrant, FrameGrantWrite, FrameProducer};
+use crate::grant::{GrantRead, GrantWrite};
+use crate::split::{Consumer, Producer};
+use crate::{Error, stats, sync};
+
+// Retries `op` for as long as it fails with `InsufficientSize` and the deadline hasn't passed.
+// `op` mustn't count its failures, only the outcome is tracked.
+#[inline]
+fn spin_until<T, R>(
+    ring: &Ring<T>,
+    side: stats::Side,
+    deadline: Instant,
+    op: impl Fn() -> Result<R, Error>,
+) -> Result<R, Error> {
+    let result = loop {
+        match op() {
+            Err(Error::InsufficientSize) if Instant::now() < deadline => hint::spin_loop(),
+            result => break result,
+        }
+    };
+
+    // one grant as far as the stats go, however many tries it took
+    sync::critical(|| stats::track(ring.counters(), side, result))
+}
+
+impl<T> Producer<'_, T> {
+    // like `grant_exact_async`, with `Err(InsufficientSize)` once `deadline` has passed
+    pub fn grant_exact_blocking(
+        &self,
+        size: usize,
+        deadline: Instant,
+    ) -> Result<GrantWrite<T>, Error> {
+        match size {
+            0 => self.grant_exact(size),
+            _ => spin_until(&self.ring, stats::Side::Write, deadline, || {
+                self.grant_exact_untracked(size)
+            }),
+        }
+    }
+}
+
+impl<T> Consumer<'_, T> {
+    // like `read_async`, with `Err(InsufficientSize)` once `deadline` has passed
+    pub fn read_blocking(&self, deadline: Instant) -> Result<GrantRead<T>, Error> {
+        spin_until(&self.ring, stats::Side::Read, deadline, || {
+            self.read_untracked()
+        })
+    }
+}
+
+impl FrameProducer<'_> {
+    pub fn grant_blocking(&self, max: usize, deadline: Instant) -> Result<FrameGrantWrite, Error> {
+        let ring = &self.producer.ring;
+        spin_until(ring, stats::Side::Write, deadline, || {
+            self.grant_untracked(max)
+        })
+    }
+}
+
+impl FrameConsumer<'_> {
+    pub fn read_blocking(&self, deadline: Instant) -> Result<FrameGrant, Error> {
+        let ring = &self.consumer.ring;
+        spin_until(ring, stats::Side::Read, deadline, || self.read_untracked())
+    }
+}
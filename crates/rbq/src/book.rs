@@ -1,7 +1,8 @@
 use core::num::NonZeroUsize;
 use core::ops::Range;
 
-use crate::{_unreachable, _unsafe_assert, Error};
+use crate::sync::{AtomicBool, AtomicUsize, Ordering};
+use crate::{_const_fn, _unreachable, _unsafe_assert, Error, sync};
 
 #[derive(Debug, Clone, Copy)]
 pub(super) struct GrantRange {
@@ -34,6 +35,13 @@ impl GrantRange {
     pub(super) fn to_len(self) -> usize {
         self.len.get()
     }
+
+    // the first `len` elements, which can't be none of them
+    #[inline]
+    pub(super) fn truncate(self, len: usize) -> Self {
+        _unsafe_assert!(len <= self.len.get());
+        Self::from_range(self.start..(self.start + len))
+    }
 }
 
 #[cold]
@@ -41,31 +49,44 @@ fn err_in_progress() -> Result<(), Error> {
     Err(Error::GrantInProgress)
 }
 
-#[derive(Debug, Clone)]
+// `repr(C)` so a persistent ring finds the indices where the previous boot left them
+#[derive(Debug)]
+#[repr(C)]
 pub(super) struct Book {
     // where the next byte will be written
-    write: usize,
+    write: AtomicUsize,
 
     // where the next byte will be read
-    last: usize,
+    read: AtomicUsize,
 
     // when inverted, marks the last valid position in the high half of the buffer
     // when it is not fully filled.
-    read: usize,
+    //
+    // the indices run up to and including the capacity, so the high half can be filled all the
+    // way to the end. the only byte ever left unused is the one between an inverted write and
+    // read, which keeps a full inverted ring apart from an empty one.
+    last: AtomicUsize,
 
     // used by the writer to remember what bytes are allowed to be written to, but are not yet ready to be read from
-    reserve: usize,
+    reserve: AtomicUsize,
 
     // enforce spsc
-    read_in_progress: bool,
-    write_in_progress: bool,
+    read_in_progress: AtomicBool,
+    write_in_progress: AtomicBool,
+
+    // set by the writer while it moves read itself, to rewind an empty ring or discard records
+    read_by_writer: AtomicBool,
+
+    // multi-producer only: reservations not yet committed, and where the newest reservation that
+    // wrapped to the start left the high half of the buffer
+    pending: AtomicUsize,
+    wrap: AtomicUsize,
 }
 
 impl Book {
     #[inline]
-    fn sm_acq_write(&mut self) -> Result<(), Error> {
-        if !self.write_in_progress {
-            self.write_in_progress = true;
+    fn sm_acq_write(&self) -> Result<(), Error> {
+        if !self.write_in_progress.swap(true, Ordering::AcqRel) {
             Ok(())
         } else {
             err_in_progress()
@@ -73,111 +94,262 @@ impl Book {
     }
 
     #[inline]
-    fn sm_rel_write(&mut self) {
-        _unsafe_assert!(self.write_in_progress);
-        self.write_in_progress = false;
+    fn sm_rel_write(&self) {
+        _unsafe_assert!(self.write_in_progress.load(Ordering::Relaxed));
+        self.write_in_progress.store(false, Ordering::Release);
     }
 
     #[inline]
-    fn sm_acq_read(&mut self) -> Result<(), Error> {
-        if !self.read_in_progress {
-            self.read_in_progress = true;
-            Ok(())
-        } else {
-            err_in_progress()
+    fn sm_acq_read(&self) -> Result<(), Error> {
+        if self.read_in_progress.swap(true, Ordering::Acquire) {
+            return err_in_progress();
+        }
+
+        // the writer is rewinding or discarding, either way there's nothing to read right now
+        sync::fence(Ordering::SeqCst);
+        if self.read_by_writer.load(Ordering::Acquire) {
+            self.sm_rel_read();
+            return Err(Error::InsufficientSize);
         }
+
+        Ok(())
     }
 
     #[inline]
-    fn sm_rel_read(&mut self) {
-        _unsafe_assert!(self.read_in_progress);
-        self.read_in_progress = false;
+    fn sm_rel_read(&self) {
+        _unsafe_assert!(self.read_in_progress.load(Ordering::Relaxed));
+        self.read_in_progress.store(false, Ordering::Release);
     }
 
-    pub(super) const fn new() -> Self {
-        Self {
-            write: 0,
-            last: 0,
-            read: 0,
-            reserve: 0,
-            read_in_progress: false,
-            write_in_progress: false,
+    _const_fn! {
+        pub(super) const fn new() -> Self {
+            Self {
+                write: AtomicUsize::new(0),
+                read: AtomicUsize::new(0),
+                last: AtomicUsize::new(0),
+                reserve: AtomicUsize::new(0),
+                read_in_progress: AtomicBool::new(false),
+                write_in_progress: AtomicBool::new(false),
+                read_by_writer: AtomicBool::new(false),
+                pending: AtomicUsize::new(0),
+                wrap: AtomicUsize::new(0),
+            }
         }
     }
 
+    // An empty ring whose indices sit in the middle of the buffer can't fit an exact grant larger
+    // than either side of them, so the writer moves it back to the start while the reader is idle.
+    // Together with `sm_acq_read` this is a dekker-style handshake: either the writer sees the read
+    // in progress and leaves the ring alone, or the reader sees the rewind and backs off. The fences
+    // rather than seqcst accesses are what make it hold, both in the memory model and under loom.
     #[inline]
-    pub(super) fn release_write(&mut self) {
+    fn try_rewind(&self, write: usize, read: usize) -> bool {
+        if write != read || write == 0 {
+            return false;
+        }
+
+        self.read_by_writer.store(true, Ordering::Relaxed);
+        sync::fence(Ordering::SeqCst);
+
+        let idle = !self.read_in_progress.load(Ordering::Relaxed)
+            && self.read.load(Ordering::Acquire) == write;
+
+        if idle {
+            self.read.store(0, Ordering::Release);
+            self.write.store(0, Ordering::Release);
+        }
+
+        self.read_by_writer.store(false, Ordering::Release);
+        idle
+    }
+
+    // Lets the writer release the first `discard(readable)` elements of what the reader would be
+    // handed next, for overwriting grants. Same handshake as `try_rewind`, so the reader never
+    // fails with `GrantInProgress` because of it. Fails if the reader holds a grant or there's
+    // nothing to read.
+    #[inline]
+    pub(super) fn try_discard(&self, discard: impl FnOnce(Range<usize>) -> usize) -> bool {
+        self.read_by_writer.store(true, Ordering::Relaxed);
+        sync::fence(Ordering::SeqCst);
+
+        let discarded = match self.read_in_progress.load(Ordering::Relaxed) {
+            true => false,
+            false => match self.readable() {
+                Some(range) => {
+                    let used = discard(range.clone());
+                    _unsafe_assert!(used <= range.len());
+                    self.read.store(range.start + used, Ordering::Release);
+                    true
+                }
+                None => false,
+            },
+        };
+
+        self.read_by_writer.store(false, Ordering::Release);
+        discarded
+    }
+
+    #[inline]
+    pub(super) fn release_write(&self) {
         self.sm_rel_write();
     }
 
     #[inline]
-    pub(super) fn release_read(&mut self) {
+    pub(super) fn release_read(&self) {
         self.sm_rel_read();
     }
 
+    // committed elements the reader hasn't released yet, in ring order. without the critical
+    // section this is only a snapshot that may be stale by the time it's returned.
+    #[inline]
+    pub(super) fn filled(&self) -> (Range<usize>, Range<usize>) {
+        let write = self.write.load(Ordering::Acquire);
+        let read = self.read.load(Ordering::Acquire);
+
+        if write >= read {
+            (read..write, 0..0)
+        } else {
+            let last = self.last.load(Ordering::Acquire);
+            (read..last.max(read), 0..write)
+        }
+    }
+
+    // releases everything `filled` returns, regardless of any read grant in progress
+    #[inline]
+    pub(super) fn clear(&self) {
+        let write = self.write.load(Ordering::Acquire);
+        self.read.store(write, Ordering::Release);
+    }
+
+    // Picks the indices back up from memory that held a book before a reset. Only the indices are
+    // read, anything that was in progress at the time is abandoned. Returns `None` when they don't
+    // describe a ring of `capacity` elements.
+    //
+    // `book` has to point to memory laid out like a book, though not necessarily a valid one.
+    #[cfg(not(loom))]
+    #[inline]
+    pub(super) unsafe fn recover(book: *const Book, capacity: usize) -> Option<Self> {
+        // the atomics have the layout of the plain integer
+        let index = |field: *const AtomicUsize| unsafe { field.cast::<usize>().read_volatile() };
+
+        let (write, read, last) = unsafe {
+            (
+                index(&raw const (*book).write),
+                index(&raw const (*book).read),
+                index(&raw const (*book).last),
+            )
+        };
+
+        let inside = write <= capacity && read <= capacity && last <= capacity;
+
+        // an inverted ring has its data between read and last, then from the start up to write
+        if !inside || (write < read && last < read) {
+            return None;
+        }
+
+        let recovered = Self::new();
+        recovered.write.store(write, Ordering::Relaxed);
+        recovered.read.store(read, Ordering::Relaxed);
+        recovered.last.store(last, Ordering::Relaxed);
+        recovered.reserve.store(write, Ordering::Relaxed);
+        Some(recovered)
+    }
+
     #[inline]
     pub(super) fn acquire_write_exact(
-        &mut self,
+        &self,
         capacity: usize,
         size: usize,
     ) -> Result<GrantRange, Error> {
+        let (range, _) = self.acquire_write_skip(capacity, size, |_| 0)?;
+        Ok(range)
+    }
+
+    // Like `acquire_write_exact`, with the grant starting `skip(start)` elements into the room it
+    // finds at `start`. Only the grant counts as reserved, the skipped elements are committed
+    // along with it (so they have to be initialized first) and given back if it commits nothing.
+    #[inline]
+    pub(super) fn acquire_write_skip(
+        &self,
+        capacity: usize,
+        size: usize,
+        skip: impl Fn(usize) -> usize,
+    ) -> Result<(GrantRange, Range<usize>), Error> {
+        // grants are never empty
+        if size == 0 {
+            return Err(Error::InsufficientSize);
+        }
+
+        // doesn't fit even from the start of an empty ring
+        if size.saturating_add(skip(0)) > capacity {
+            return Err(Error::TooLarge);
+        }
+
         self.sm_acq_write()?;
 
-        let max = capacity - 1; // TODO: should it be minus 1?
-        let inverted = self.write < self.read;
+        let mut write = self.write.load(Ordering::Acquire);
+        let mut read = self.read.load(Ordering::Acquire);
+        let max = capacity;
+        // where a grant placed at `start` ends
+        let end = |start: usize| start + skip(start) + size;
+
+        if end(write) > max && self.try_rewind(write, read) {
+            (write, read) = (0, 0);
+        }
+
+        let inverted = write < read;
 
         let start = match () {
             // inverted, room is still available
-            _ if inverted && (self.write + size) < self.read => self.write,
+            _ if inverted && end(write) < read => write,
             // inverted, no room is available
-            _ if inverted && (self.write + size) >= self.read => {
+            _ if inverted => {
                 self.sm_rel_write();
                 return Err(Error::InsufficientSize);
             }
             // non inverted condition
-            _ if !inverted && self.write + size <= max => self.write,
+            _ if end(write) <= max => write,
             // not inverted, but need to invert
-            _ if !inverted && self.write + size > max => {
-                // note: we check sz < read, not <=, because
-                // write must never == read in an inverted condition, since
-                // we will then not be able to tell if we are inverted or not
-                if size < self.read {
-                    // invertible situation
-                    0
-                } else {
-                    // not invertible, no space
-                    self.sm_rel_write();
-                    return Err(Error::InsufficientSize);
-                }
+            // note: we check against read with <, not <=, because
+            // write must never == read in an inverted condition, since
+            // we will then not be able to tell if we are inverted or not
+            _ if end(0) < read => 0,
+            // not invertible, no space
+            _ => {
+                self.sm_rel_write();
+                return Err(Error::InsufficientSize);
             }
-            _ => _unreachable!(),
         };
 
-        self.reserve = start + size;
-        let grant_range = start..(start + size);
-        Ok(GrantRange::from_range(grant_range))
+        let first = start + skip(start);
+        self.reserve.store(first + size, Ordering::Release);
+        let grant_range = first..(first + size);
+        Ok((GrantRange::from_range(grant_range), start..first))
     }
 
     #[inline]
-    pub(super) fn commit_write_exact(&mut self, capacity: usize, size: usize, used: usize) {
+    pub(super) fn commit_write_exact(&self, capacity: usize, size: usize, used: usize) {
         _unsafe_assert!(used <= size);
 
         // saturate the grant commit
         let len = size;
         //let used = cmp::min(len, used);
 
-        self.reserve -= len - used;
+        let write = self.write.load(Ordering::Acquire);
+        let reserve = self.reserve.load(Ordering::Acquire) - (len - used);
+        self.reserve.store(reserve, Ordering::Release);
 
-        let max = capacity - 1; // TODO: should it be minus 1?
-        let new_write = self.reserve;
+        let max = capacity;
+        let last = self.last.load(Ordering::Acquire);
+        let new_write = reserve;
 
         match () {
             // We have already wrapped, but we are skipping some bytes at the end of the ring.
             // Mark `last` where the write pointer used to be to hold the line here
-            _ if (new_write < self.write) && (self.write != max) => {
-                self.last = self.write;
+            _ if (new_write < write) && (write != max) => {
+                self.last.store(write, Ordering::Release);
             }
-            _ if new_write > self.last => {
+            _ if new_write > last => {
                 // We're about to pass the last pointer, which was previously the artificial
                 // end of the ring. Now that we've passed it, we can "unlock" the section
                 // that was previously skipped.
@@ -185,7 +357,7 @@ impl Book {
                 // Since new_write is strictly larger than last, it is safe to move this as
                 // the other thread will still be halted by the (about to be updated) write
                 // value.
-                self.last = max;
+                self.last.store(max, Ordering::Release);
             }
             // else: If new_write == last, either:
             // * last == max, so no need to write, OR
@@ -195,46 +367,255 @@ impl Book {
             _ => {}
         }
 
-        self.write = new_write;
+        self.write.store(new_write, Ordering::Release);
         self.sm_rel_write();
     }
 
     #[inline]
-    pub(super) fn acquire_write_remaining(
-        &mut self,
-        _capacity: usize,
-    ) -> Result<GrantRange, Error> {
-        unimplemented!()
+    pub(super) fn acquire_write_remaining(&self, capacity: usize) -> Result<GrantRange, Error> {
+        self.sm_acq_write()?;
+
+        let mut write = self.write.load(Ordering::Acquire);
+        let mut read = self.read.load(Ordering::Acquire);
+        let max = capacity;
+
+        if self.try_rewind(write, read) {
+            (write, read) = (0, 0);
+        }
+
+        let inverted = write < read;
+
+        let (start, size) = match () {
+            // inverted, hand out everything up to (but not including) the byte before read
+            _ if inverted && read - write > 1 => (write, read - write - 1),
+            // inverted, no room is available
+            _ if inverted => {
+                self.sm_rel_write();
+                return Err(Error::InsufficientSize);
+            }
+            // non inverted, some room is left at the end of the ring
+            _ if write != max => (write, max - write),
+            // not inverted, but at the end of the ring. wrap around if there's room at the start.
+            // as with exact grants, write must never catch up to read after the inversion
+            _ if read > 1 => (0, read - 1),
+            // not invertible, no space
+            _ => {
+                self.sm_rel_write();
+                return Err(Error::InsufficientSize);
+            }
+        };
+
+        self.reserve.store(start + size, Ordering::Release);
+        let grant_range = start..(start + size);
+        Ok(GrantRange::from_range(grant_range))
+    }
+
+    // Multi-producer reservations. Writers are serialized by the caller and hand out disjoint
+    // ranges starting at `reserve`, the end of the newest reservation. `write` only catches up
+    // once every outstanding reservation has been committed, so the reader sees them in
+    // reservation order and never a range that is still being written.
+    //
+    // Returns the reservation along with where the previous one ended.
+    #[inline]
+    pub(super) fn acquire_write_multi(
+        &self,
+        capacity: usize,
+        size: usize,
+    ) -> Result<(GrantRange, usize), Error> {
+        if size == 0 {
+            return Err(Error::InsufficientSize);
+        }
+
+        if size > capacity {
+            return Err(Error::TooLarge);
+        }
+
+        let pending = self.pending.load(Ordering::Relaxed);
+        let mut head = match pending {
+            0 => self.write.load(Ordering::Acquire),
+            _ => self.reserve.load(Ordering::Relaxed),
+        };
+        let mut read = self.read.load(Ordering::Acquire);
+        let max = capacity;
+
+        if pending == 0 && head + size > max && self.try_rewind(head, read) {
+            (head, read) = (0, 0);
+        }
+
+        let inverted = head < read;
+
+        // same rules as `acquire_write_exact`, with the newest reservation standing in for write
+        let start = match () {
+            _ if inverted && (head + size) < read => head,
+            _ if inverted => return Err(Error::InsufficientSize),
+            _ if head + size <= max => head,
+            _ if size < read => {
+                self.wrap.store(head, Ordering::Relaxed);
+                0
+            }
+            _ => return Err(Error::InsufficientSize),
+        };
+
+        self.reserve.store(start + size, Ordering::Relaxed);
+        self.pending.store(pending + 1, Ordering::Relaxed);
+        let grant_range = start..(start + size);
+        Ok((GrantRange::from_range(grant_range), head))
+    }
+
+    // hands the space of a dropped reservation back, which only works if it is still the newest
+    #[inline]
+    pub(super) fn unreserve_multi(&self, range: GrantRange, prev: usize) -> bool {
+        if self.reserve.load(Ordering::Relaxed) != range.to_range().end {
+            return false;
+        }
+
+        self.reserve.store(prev, Ordering::Relaxed);
+        true
     }
 
+    // returns whether the reader can see anything new
     #[inline]
-    pub(super) fn acquire_read(&mut self, capacity: usize) -> Result<GrantRange, Error> {
+    pub(super) fn commit_write_multi(&self, capacity: usize) -> bool {
+        let pending = self.pending.load(Ordering::Relaxed);
+        _unsafe_assert!(pending > 0);
+        self.pending.store(pending - 1, Ordering::Relaxed);
+
+        if pending > 1 {
+            return false;
+        }
+
+        let max = capacity;
+        let write = self.write.load(Ordering::Acquire);
+        let last = self.last.load(Ordering::Acquire);
+        let new_write = self.reserve.load(Ordering::Relaxed);
+
+        // see `commit_write_exact`. a batch wraps at most once, since it can't overtake read.
+        if new_write < write {
+            self.last
+                .store(self.wrap.load(Ordering::Relaxed), Ordering::Release);
+        } else if new_write > last {
+            self.last.store(max, Ordering::Release);
+        }
+
+        self.write.store(new_write, Ordering::Release);
+        new_write != write
+    }
+
+    #[inline]
+    pub(super) fn acquire_read(&self) -> Result<GrantRange, Error> {
         self.sm_acq_read()?;
 
+        match self.readable() {
+            Some(range) => Ok(GrantRange::from_range(range)),
+            None => {
+                self.sm_rel_read();
+                Err(Error::InsufficientSize)
+            }
+        }
+    }
+
+    // what a read grant would cover, for whichever side holds the read side
+    #[inline]
+    fn readable(&self) -> Option<Range<usize>> {
+        let write = self.write.load(Ordering::Acquire);
+        let last = self.last.load(Ordering::Acquire);
+        let mut read = self.read.load(Ordering::Acquire);
+
         // untangle the inversion by moving back read
-        if (self.read == self.last) && (self.write < self.read) {
-            self.read = 0;
+        if (read == last) && (write < read) {
+            read = 0;
+            self.read.store(0, Ordering::Release);
         }
 
         // either there's nothing to read, we're in normal form, or inverted
         let sz = match () {
-            _ if self.write == self.read => {
+            _ if write == read => return None,
+            _ if write > read => write - read,
+            // inverted, only read up to the artificial end of the ring. the wrapped part is
+            // picked up by the next read once read has been moved back to the start.
+            _ if write < read => last - read,
+            _ => _unreachable!(),
+        };
+
+        Some(read..(read + sz))
+    }
+
+    #[inline]
+    pub(super) fn acquire_read_split(&self) -> Result<(GrantRange, Option<GrantRange>), Error> {
+        self.sm_acq_read()?;
+
+        let write = self.write.load(Ordering::Acquire);
+        let last = self.last.load(Ordering::Acquire);
+        let mut read = self.read.load(Ordering::Relaxed);
+
+        // untangle the inversion by moving back read
+        if (read == last) && (write < read) {
+            read = 0;
+            self.read.store(0, Ordering::Release);
+        }
+
+        // when inverted, the tail runs up to the artificial end of the ring and the head
+        // picks up from the start of the buffer
+        let (tail, head) = match () {
+            _ if write == read => {
                 self.sm_rel_read();
                 return Err(Error::InsufficientSize);
             }
-            _ if self.write > self.read => self.write - self.read,
-            _ if self.write < self.read => (capacity - self.read) + self.write, // TODO: minus one capacity?
+            _ if write > read => (write - read, 0),
+            _ if write < read => (last - read, write),
             _ => _unreachable!(),
         };
 
-        let grant_range = self.read..(self.read + sz);
-        Ok(GrantRange::from_range(grant_range))
+        let tail_range = GrantRange::from_range(read..(read + tail));
+        let head_range = (head != 0).then(|| GrantRange::from_range(0..head));
+        Ok((tail_range, head_range))
+    }
+
+    // like `acquire_read_split`, once at least `min` elements can be read
+    #[inline]
+    pub(super) fn acquire_read_min(
+        &self,
+        capacity: usize,
+        min: usize,
+    ) -> Result<(GrantRange, Option<GrantRange>), Error> {
+        if min == 0 {
+            return Err(Error::InsufficientSize);
+        }
+
+        if min > capacity {
+            return Err(Error::TooLarge);
+        }
+
+        let (tail, head) = self.acquire_read_split()?;
+
+        if tail.to_len() + head.map_or(0, GrantRange::to_len) < min {
+            self.sm_rel_read();
+            return Err(Error::InsufficientSize);
+        }
+
+        Ok((tail, head))
+    }
+
+    #[inline]
+    pub(super) fn commit_read_split(&self, tail: usize, size: usize, used: usize) {
+        _unsafe_assert!(used <= size);
+
+        if used <= tail {
+            let read = self.read.load(Ordering::Relaxed);
+            self.read.store(read + used, Ordering::Release);
+        } else {
+            // the commit reaches into the head, which starts at the beginning of the buffer
+            self.read.store(used - tail, Ordering::Release);
+        }
+
+        self.sm_rel_read();
     }
 
     #[inline]
-    pub(super) fn commit_read(&mut self, size: usize, used: usize) {
+    pub(super) fn commit_read(&self, size: usize, used: usize) {
         _unsafe_assert!(used <= size);
-        self.read += used;
+        let read = self.read.load(Ordering::Relaxed);
+        self.read.store(read + used, Ordering::Release);
         self.sm_rel_read();
     }
 }
@@ -0,0 +1,558 @@
+// Broadcast mode for byte rings: one producer and a fixed number of consumers, each with its own
+// read cursor and draining at its own pace.
+//
+// Every consumer sees every byte. The producer's free space is whatever the slowest consumer has
+// left, unless the ring was split with `Slowest::Lags`. Then a consumer that is in the way of a
+// write is left behind instead: its next read fails with `Error::Lagged`, after which it picks up
+// again at the oldest data still in the ring. A consumer holding a read grant is never left
+// behind, the write fails like a regular one on a full ring.
+//
+// The cursors live in a `Readers` next to the buffer, so the ring's stats take `used` from the
+// slowest cursor, and count what every consumer reads in `read`. All of the bookkeeping runs inside a real
+// critical section, with or without the `atomic` feature, since a commit touches every cursor.
+// Cursors count the bytes they haven't read rather than relying on a gap between the indices, and
+// those counts include the bytes a wrapping grant skipped at the end of the ring.
+
+use core::marker::PhantomData;
+use core::ops::Range;
+use core::{fmt, mem};
+
+use crate::buffer::Ring;
+use crate::split::NotSync;
+use crate::sync::{AtomicBool, AtomicUsize, AtomicWaker, Ordering};
+use crate::wait::PollFn;
+use crate::{_const_fn, Error, stats};
+
+// what happens to the producer when the slowest consumer is in the way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Slowest {
+    // it waits for the consumer, like it would with a single one
+    Blocks,
+    // the consumer is left behind and told so on its next read
+    Lags,
+}
+
+#[derive(Debug)]
+struct Shared {
+    // where the next byte will be written
+    write: AtomicUsize,
+    // the end of the data in the high half of the buffer, once a grant has wrapped
+    last: AtomicUsize,
+    writing: AtomicBool,
+    lags: AtomicBool,
+    split: AtomicBool,
+}
+
+struct Cursor {
+    // where the next byte will be read
+    read: AtomicUsize,
+    // committed bytes not read yet, including any skipped at the end of the ring
+    unread: AtomicUsize,
+    reading: AtomicBool,
+    lagged: AtomicBool,
+    // woken by write commits
+    waker: AtomicWaker,
+}
+
+impl Cursor {
+    _const_fn! {
+        const fn new() -> Self {
+            Self {
+                read: AtomicUsize::new(0),
+                unread: AtomicUsize::new(0),
+                reading: AtomicBool::new(false),
+                lagged: AtomicBool::new(false),
+                waker: AtomicWaker::new(),
+            }
+        }
+    }
+
+    // loom's wakers can't be created in const context, see `internal_const_fn`
+    #[cfg(not(loom))]
+    const fn array<const R: usize>() -> [Self; R] {
+        [const { Self::new() }; R]
+    }
+
+    #[cfg(loom)]
+    fn array<const R: usize>() -> [Self; R] {
+        core::array::from_fn(|_| Self::new())
+    }
+
+    #[inline]
+    fn active(&self) -> bool {
+        !self.lagged.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cursor")
+            .field("read", &self.read)
+            .field("unread", &self.unread)
+            .finish_non_exhaustive()
+    }
+}
+
+// The read side of a broadcast ring, with one cursor for each of the `R` consumers.
+#[derive(Debug)]
+pub struct Readers<const R: usize> {
+    shared: Shared,
+    cursors: [Cursor; R],
+}
+
+impl<const R: usize> Readers<R> {
+    _const_fn! {
+        pub const fn new() -> Self {
+            Self {
+                shared: Shared {
+                    write: AtomicUsize::new(0),
+                    last: AtomicUsize::new(0),
+                    writing: AtomicBool::new(false),
+                    lags: AtomicBool::new(false),
+                    split: AtomicBool::new(false),
+                },
+                cursors: Cursor::array(),
+            }
+        }
+    }
+}
+
+impl<const R: usize> Default for Readers<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// only ever touched inside a critical section
+unsafe impl Send for Shared {}
+unsafe impl Sync for Shared {}
+unsafe impl Send for Cursor {}
+unsafe impl Sync for Cursor {}
+
+// The bookkeeping, always called inside a critical section.
+#[derive(Debug, Clone, Copy)]
+struct Book<'a> {
+    shared: &'a Shared,
+    cursors: &'a [Cursor],
+}
+
+impl Book<'_> {
+    // the active cursor with the most left to read, which is the one furthest behind
+    #[inline]
+    fn slowest(&self) -> Option<&Cursor> {
+        self.cursors
+            .iter()
+            .filter(|cursor| cursor.active())
+            .max_by_key(|cursor| cursor.unread.load(Ordering::Relaxed))
+    }
+
+    // what the slowest consumer has left to read, which is what the producer can't write over
+    #[inline]
+    fn filled(&self) -> usize {
+        self.slowest()
+            .map_or(0, |cursor| cursor.unread.load(Ordering::Relaxed))
+    }
+
+    #[inline]
+    fn acquire_write(&self, capacity: usize, size: usize) -> Result<Range<usize>, Error> {
+        // grants are never empty
+        if size == 0 {
+            return Err(Error::InsufficientSize);
+        }
+
+        if size > capacity {
+            return Err(Error::TooLarge);
+        }
+
+        if self.shared.writing.load(Ordering::Relaxed) {
+            return Err(Error::GrantInProgress);
+        }
+
+        loop {
+            let write = self.shared.write.load(Ordering::Relaxed);
+            let slowest = self.slowest();
+            let (read, unread) = match slowest {
+                Some(cursor) => (
+                    cursor.read.load(Ordering::Relaxed),
+                    cursor.unread.load(Ordering::Relaxed),
+                ),
+                None => (write, 0),
+            };
+
+            let start = match () {
+                // nothing left to read anywhere, so everyone can move back to the start
+                _ if unread == 0 && write + size > capacity => {
+                    self.rewind(capacity);
+                    Some(0)
+                }
+                // inverted, the slowest consumer is ahead of the producer in the buffer
+                _ if unread > 0 && write <= read => (write + size <= read).then_some(write),
+                _ if write + size <= capacity => Some(write),
+                // wrap around, skipping the rest of the buffer
+                _ => (size <= read).then_some(0),
+            };
+
+            if let Some(start) = start {
+                self.shared.writing.store(true, Ordering::Relaxed);
+                return Ok(start..(start + size));
+            }
+
+            match slowest {
+                Some(cursor) if self.may_lag(cursor) => {
+                    cursor.lagged.store(true, Ordering::Relaxed);
+                    cursor.waker.wake();
+                }
+                _ => return Err(Error::InsufficientSize),
+            }
+        }
+    }
+
+    #[inline]
+    fn may_lag(&self, cursor: &Cursor) -> bool {
+        self.shared.lags.load(Ordering::Relaxed) && !cursor.reading.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    fn rewind(&self, capacity: usize) {
+        for cursor in self.cursors.iter().filter(|cursor| cursor.active()) {
+            cursor.read.store(0, Ordering::Relaxed);
+        }
+
+        self.shared.write.store(0, Ordering::Relaxed);
+        self.shared.last.store(capacity, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn commit_write(&self, capacity: usize, start: usize, used: usize) {
+        self.shared.writing.store(false, Ordering::Relaxed);
+
+        if used == 0 {
+            return;
+        }
+
+        let write = self.shared.write.load(Ordering::Relaxed);
+        let last = self.shared.last.load(Ordering::Relaxed);
+        let new_write = start + used;
+
+        // see `Book::commit_write_exact`
+        let skipped = if start < write {
+            self.shared.last.store(write, Ordering::Relaxed);
+            capacity - write
+        } else {
+            if new_write > last {
+                self.shared.last.store(capacity, Ordering::Relaxed);
+            }
+            0
+        };
+
+        for cursor in self.cursors.iter().filter(|cursor| cursor.active()) {
+            let unread = cursor.unread.load(Ordering::Relaxed);
+            cursor
+                .unread
+                .store(unread + skipped + used, Ordering::Relaxed);
+        }
+
+        self.shared.write.store(new_write, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn acquire_read(&self, capacity: usize, index: usize) -> Result<Range<usize>, Error> {
+        let cursor = &self.cursors[index];
+
+        if cursor.reading.load(Ordering::Relaxed) {
+            return Err(Error::GrantInProgress);
+        }
+
+        // catch up with the oldest data still in the ring
+        if !cursor.active() {
+            let write = self.shared.write.load(Ordering::Relaxed);
+            let (read, unread) = match self.slowest() {
+                Some(slowest) => (
+                    slowest.read.load(Ordering::Relaxed),
+                    slowest.unread.load(Ordering::Relaxed),
+                ),
+                None => (write, 0),
+            };
+
+            cursor.read.store(read, Ordering::Relaxed);
+            cursor.unread.store(unread, Ordering::Relaxed);
+            cursor.lagged.store(false, Ordering::Relaxed);
+            return Err(Error::Lagged);
+        }
+
+        let write = self.shared.write.load(Ordering::Relaxed);
+        let last = self.shared.last.load(Ordering::Relaxed);
+        let mut read = cursor.read.load(Ordering::Relaxed);
+        let unread = cursor.unread.load(Ordering::Relaxed);
+
+        if unread == 0 {
+            return Err(Error::InsufficientSize);
+        }
+
+        // inverted and at the end of the data in the high half, skip to the start
+        if write <= read && read == last {
+            read = 0;
+            cursor.read.store(0, Ordering::Relaxed);
+            cursor
+                .unread
+                .store(unread - (capacity - last), Ordering::Relaxed);
+        }
+
+        let len = match read < write {
+            true => write - read,
+            false => last - read,
+        };
+
+        cursor.reading.store(true, Ordering::Relaxed);
+        Ok(read..(read + len))
+    }
+
+    #[inline]
+    fn commit_read(&self, index: usize, start: usize, used: usize) {
+        let cursor = &self.cursors[index];
+        cursor.reading.store(false, Ordering::Relaxed);
+
+        let unread = cursor.unread.load(Ordering::Relaxed);
+        cursor.read.store(start + used, Ordering::Relaxed);
+        cursor.unread.store(unread - used, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug)]
+pub struct BroadcastProducer<'a> {
+    ring: Ring<'a>,
+    book: Book<'a>,
+    _not_sync: NotSync,
+}
+
+impl BroadcastProducer<'_> {
+    #[inline(never)]
+    pub fn grant_exact(&self, size: usize) -> Result<BroadcastGrantWrite, Error> {
+        let ring = &self.ring;
+        let capacity = ring.capacity();
+        let range = critical_section::with(|_| {
+            let result = self.book.acquire_write(capacity, size);
+            // leaving a consumer behind changes which one is the slowest
+            ring.counters().on_fill(self.book.filled());
+            stats::track(ring.counters(), stats::Side::Write, result)
+        })?;
+        let grant = BroadcastGrantWrite {
+            producer: self,
+            range,
+        };
+        Ok(grant)
+    }
+
+    #[inline]
+    pub fn poll<'b, F, R>(&'b self, op: F) -> PollFn<'b, Self, F>
+    where
+        F: Fn(&'b Self) -> Option<R>,
+    {
+        PollFn::new(self, self.ring.write_waker(), op)
+    }
+
+    // waits until `size` bytes are free. fails right away for grants that can never fit.
+    pub async fn grant_exact_async(&self, size: usize) -> Result<BroadcastGrantWrite, Error> {
+        self.poll(|p| match p.grant_exact(size) {
+            Err(Error::InsufficientSize) if size > 0 => None,
+            result => Some(result),
+        })
+        .await
+    }
+}
+
+#[derive(Debug)]
+pub struct BroadcastConsumer<'a> {
+    ring: Ring<'a>,
+    book: Book<'a>,
+    index: usize,
+    _not_sync: NotSync,
+}
+
+impl BroadcastConsumer<'_> {
+    #[inline(never)]
+    pub fn read(&self) -> Result<BroadcastGrantRead, Error> {
+        let ring = &self.ring;
+        let capacity = ring.capacity();
+        let range = critical_section::with(|_| {
+            let result = self.book.acquire_read(capacity, self.index);
+            // so does catching up after being left behind
+            ring.counters().on_fill(self.book.filled());
+            stats::track(ring.counters(), stats::Side::Read, result)
+        })?;
+        let grant = BroadcastGrantRead {
+            consumer: self,
+            range,
+        };
+        Ok(grant)
+    }
+
+    #[inline]
+    pub fn poll<'b, F, R>(&'b self, op: F) -> PollFn<'b, Self, F>
+    where
+        F: Fn(&'b Self) -> Option<R>,
+    {
+        PollFn::new(self, &self.book.cursors[self.index].waker, op)
+    }
+
+    // waits until there is data to read, or this consumer has been left behind
+    pub async fn read_async(&self) -> Result<BroadcastGrantRead, Error> {
+        self.poll(|c| match c.read() {
+            Err(Error::InsufficientSize) => None,
+            result => Some(result),
+        })
+        .await
+    }
+}
+
+#[must_use]
+#[derive(Debug)]
+pub struct BroadcastGrantWrite<'a> {
+    producer: &'a BroadcastProducer<'a>,
+    range: Range<usize>,
+}
+
+impl BroadcastGrantWrite<'_> {
+    #[inline]
+    pub fn buf(&self) -> &[u8] {
+        unsafe { self.producer.ring.view(self.range.clone()) }
+    }
+
+    #[inline]
+    pub fn buf_mut(&mut self) -> &mut [u8] {
+        unsafe { &mut *(self.producer.ring.slots(self.range.clone()) as *mut [u8]) }
+    }
+
+    // every consumer gets the first `used` bytes. dropping the grant instead commits none.
+    #[inline]
+    pub fn commit(mut self, used: usize) {
+        self.commit_internal(used);
+        mem::forget(self);
+    }
+
+    #[inline(never)]
+    fn commit_internal(&mut self, used: usize) {
+        let producer = self.producer;
+        let capacity = producer.ring.capacity();
+        let start = self.range.start;
+        let used = used.min(self.range.len());
+
+        critical_section::with(|_| {
+            let book = producer.book;
+            book.commit_write(capacity, start, used);
+            producer
+                .ring
+                .counters()
+                .on_write_filled(used, book.filled());
+        });
+
+        if used > 0 {
+            for cursor in producer.book.cursors {
+                cursor.waker.wake();
+            }
+        }
+    }
+}
+
+impl Drop for BroadcastGrantWrite<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        self.commit_internal(0);
+    }
+}
+
+#[must_use]
+#[derive(Debug)]
+pub struct BroadcastGrantRead<'a> {
+    consumer: &'a BroadcastConsumer<'a>,
+    range: Range<usize>,
+}
+
+impl BroadcastGrantRead<'_> {
+    #[inline]
+    pub fn buf(&self) -> &[u8] {
+        unsafe { self.consumer.ring.view(self.range.clone()) }
+    }
+
+    // releases the first `used` bytes for this consumer only
+    #[inline]
+    pub fn commit(mut self, used: usize) {
+        self.commit_internal(used);
+        mem::forget(self);
+    }
+
+    #[inline(never)]
+    fn commit_internal(&mut self, used: usize) {
+        let consumer = self.consumer;
+        let start = self.range.start;
+        let used = used.min(self.range.len());
+
+        critical_section::with(|_| {
+            let book = consumer.book;
+            book.commit_read(consumer.index, start, used);
+            consumer.ring.counters().on_read(used);
+            consumer.ring.counters().on_fill(book.filled());
+        });
+
+        if used > 0 {
+            consumer.ring.write_waker().wake();
+        }
+    }
+}
+
+impl Drop for BroadcastGrantRead<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        self.commit_internal(0);
+    }
+}
+
+impl<'a> Ring<'a> {
+    // Like `split`, with a consumer for each of the cursors in `readers`. What `slowest` says
+    // applies for as long as the ring is in use.
+    #[inline(never)]
+    pub fn split_broadcast<const R: usize>(
+        &self,
+        readers: &'a Readers<R>,
+        slowest: Slowest,
+    ) -> Result<(BroadcastProducer<'a>, [BroadcastConsumer<'a>; R]), Error> {
+        let book = Book {
+            shared: &readers.shared,
+            cursors: &readers.cursors,
+        };
+
+        // neither the ring nor the readers are touched unless both are still free
+        let taken = critical_section::with(|_| {
+            if book.shared.split.load(Ordering::Relaxed) || self.flag_split() {
+                return true;
+            }
+
+            book.shared.split.store(true, Ordering::Relaxed);
+            book.shared
+                .lags
+                .store(slowest == Slowest::Lags, Ordering::Relaxed);
+            self.counters().on_fill(book.filled());
+            false
+        });
+
+        if taken {
+            return Err(Error::AlreadySplit);
+        }
+
+        let producer = BroadcastProducer {
+            ring: self.alias(),
+            book,
+            _not_sync: PhantomData,
+        };
+
+        let consumers = core::array::from_fn(|index| BroadcastConsumer {
+            ring: self.alias(),
+            book,
+            index,
+            _not_sync: PhantomData,
+        });
+
+        Ok((producer, consumers))
+    }
+}
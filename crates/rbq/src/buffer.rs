@@ -1,110 +1,320 @@
 use core::cell::UnsafeCell;
-use core::marker::PhantomData;
-use core::mem::MaybeUninit;
+use core::mem::{self, MaybeUninit};
 use core::ops::Range;
+use core::{fmt, ptr};
 
-use critical_section::CriticalSection;
-use embassy_sync::waitqueue::WakerRegistration;
-
-use crate::Error;
 use crate::book::Book;
-use crate::grant::{GrantRead, GrantWrite};
+use crate::frame::{FrameConsumer, FrameProducer};
+use crate::multi::MultiProducer;
+use crate::split::{Consumer, Producer};
+use crate::stats::Counters;
+#[cfg(feature = "stats")]
+use crate::stats::Stats;
+use crate::sync::{AtomicBool, AtomicUsize, AtomicWaker, Ordering};
+use crate::{_const_fn, _unsafe_assert, Error, grant, sync};
+
+// Every element lives in its own cell, so handles only ever hold shared references to the storage
+// and grants turn their range into a slice through a raw pointer. The bookkeeping is kept apart
+// from the storage and is only touched through atomics or cells, which is what keeps a grant's
+// `&mut` slice from aliasing anything else.
+pub(crate) type Slot<T> = UnsafeCell<MaybeUninit<T>>;
+
+// Loom only sees the accesses that go through its own cells, but the slots have to stay plain so a
+// range of them is still one slice. Under loom every slot gets a loom cell alongside it instead,
+// which `Ring::slots` and `Ring::view` go through to stand in for the access to the slot itself.
+#[cfg(loom)]
+pub(crate) type Shadow = loom::cell::UnsafeCell<()>;
 
-pub(crate) struct Dst<T: ?Sized> {
+#[repr(C)]
+pub(crate) struct Header {
     pub(crate) book: Book,
-    pub(crate) waker: WakerRegistration,
-    pub(crate) buf: T,
+    // woken by write commits, for the consumer waiting on data
+    pub(crate) read_waker: AtomicWaker,
+    // woken by read commits, for the producer waiting on free space
+    pub(crate) write_waker: AtomicWaker,
+    pub(crate) split: AtomicBool,
+    // records discarded by overwriting grants, only ever moved forward by the producer
+    pub(crate) dropped_records: AtomicUsize,
+    pub(crate) dropped_bytes: AtomicUsize,
+    pub(crate) counters: Counters,
 }
 
-pub struct Buffer<const N: usize> {
-    dst: UnsafeCell<Dst<[MaybeUninit<u8>; N]>>,
+impl Header {
+    _const_fn! {
+        pub(crate) const fn new() -> Self {
+            Self {
+                book: Book::new(),
+                read_waker: AtomicWaker::new(),
+                write_waker: AtomicWaker::new(),
+                split: AtomicBool::new(false),
+                dropped_records: AtomicUsize::new(0),
+                dropped_bytes: AtomicUsize::new(0),
+                counters: Counters::new(),
+            }
+        }
+    }
 }
 
-impl<const N: usize> Buffer<N> {
-    pub const fn new() -> Self {
-        Self {
-            dst: UnsafeCell::new(Dst {
-                book: Book::new(),
-                waker: WakerRegistration::new(),
-                buf: MaybeUninit::uninit_array(),
-            }),
+// `T` is the element type, bytes unless stated otherwise. Elements still in the ring when the
+// buffer goes away are dropped with it.
+pub struct Buffer<const N: usize, T = u8> {
+    header: Header,
+    // zeroed rather than uninitialized, so byte grants can be handed out as plain `[u8]`. in a
+    // static this costs nothing, it ends up in .bss either way.
+    slots: [Slot<T>; N],
+    #[cfg(loom)]
+    shadows: [Shadow; N],
+}
+
+impl<const N: usize, T> Buffer<N, T> {
+    _const_fn! {
+        pub const fn new() -> Self {
+            Self {
+                header: Header::new(),
+                slots: [const { UnsafeCell::new(MaybeUninit::zeroed()) }; N],
+                #[cfg(loom)]
+                shadows: core::array::from_fn(|_| Shadow::new(())),
+            }
         }
     }
+}
 
-    const fn dst(&self) -> *mut Dst<[MaybeUninit<u8>]> {
-        let r1: &UnsafeCell<Dst<[MaybeUninit<u8>; N]>> = &self.dst;
-        let r2: &UnsafeCell<Dst<[MaybeUninit<u8>]>> = r1;
-        r2.get()
+impl<const N: usize, T> Drop for Buffer<N, T> {
+    fn drop(&mut self) {
+        if !mem::needs_drop::<T>() {
+            return;
+        }
+
+        let (tail, head) = self.header.book.filled();
+
+        for range in [tail, head] {
+            for slot in &mut self.slots[range] {
+                unsafe { slot.get_mut().assume_init_drop() };
+            }
+        }
     }
 }
 
-unsafe impl<const N: usize> Send for Buffer<N> {}
-unsafe impl<const N: usize> Sync for Buffer<N> {}
+unsafe impl<const N: usize, T: Send> Send for Buffer<N, T> {}
+unsafe impl<const N: usize, T: Send> Sync for Buffer<N, T> {}
+
+pub type TypedBuffer<T, const N: usize> = Buffer<N, T>;
 
-#[derive(Debug)]
-pub struct Ring<'a> {
-    dst: *mut Dst<[MaybeUninit<u8>]>,
-    _marker: PhantomData<&'a ()>,
+pub struct Ring<'a, T = u8> {
+    header: &'a Header,
+    slots: &'a [Slot<T>],
+    // empty for rings over borrowed memory, which loom doesn't check
+    #[cfg(loom)]
+    shadows: &'a [Shadow],
 }
 
-impl<'a> Ring<'a> {
-    pub const fn new<const N: usize>(buffer: &'a Buffer<N>) -> Self {
+impl<'a, T> Ring<'a, T> {
+    pub const fn new<const N: usize>(buffer: &'a Buffer<N, T>) -> Self {
+        Self {
+            header: &buffer.header,
+            slots: &buffer.slots,
+            #[cfg(loom)]
+            shadows: &buffer.shadows,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn from_parts(header: &'a Header, slots: &'a [Slot<T>]) -> Self {
         Self {
-            dst: buffer.dst(),
-            _marker: PhantomData,
+            header,
+            slots,
+            #[cfg(loom)]
+            shadows: &[],
         }
     }
 
     #[inline(never)]
-    pub fn grant_exact(&self, cs: CriticalSection, size: usize) -> Result<GrantWrite, Error> {
-        let dst = self._dst(cs);
-        let capacity = dst.buf.len();
-        let range = dst.book.acquire_write_exact(capacity, size)?;
-        let grant = GrantWrite { ring: self, range };
-        Ok(grant)
+    pub fn split(&self) -> Result<(Producer<'a, T>, Consumer<'a, T>), Error> {
+        if sync::critical(|| self.flag_split()) {
+            return Err(Error::AlreadySplit);
+        }
+
+        Ok((Producer::new(self.alias()), Consumer::new(self.alias())))
     }
 
+    /// Like `Consumer::drain`, for a panic handler that can't reach the consumer, or that stopped
+    /// it halfway through a read grant. Hands everything committed to `f` and empties the ring,
+    /// whatever grants are in progress. Returns how many elements that was.
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may touch the ring while this runs, and no grant taken before it may be used
+    /// again afterwards.
     #[inline(never)]
-    pub fn grant_max_remaining(&self, cs: CriticalSection) -> Result<GrantWrite, Error> {
-        let dst = self._dst(cs);
-        let capacity = dst.buf.len();
-        let range = dst.book.acquire_write_remaining(capacity)?;
-        let grant = GrantWrite { ring: self, range };
-        Ok(grant)
+    pub unsafe fn drain_unchecked(&self, mut f: impl FnMut(&[T])) -> usize {
+        sync::critical(|| {
+            let book = self.book();
+            let (tail, head) = book.filled();
+            let drained = tail.len() + head.len();
+
+            for range in [tail, head] {
+                if !range.is_empty() {
+                    f(unsafe { self.view(range.clone()) });
+                    unsafe { grant::drop_elements(self, range) };
+                }
+            }
+
+            book.clear();
+            self.counters().on_read(drained);
+            drained
+        })
     }
 
+    #[cfg(feature = "stats")]
     #[inline(never)]
-    pub fn read(&self, cs: CriticalSection) -> Result<GrantRead, Error> {
-        let dst = self._dst(cs);
-        let capacity = dst.buf.len();
-        let range = dst.book.acquire_read(capacity)?;
-        let grant = GrantRead { ring: self, range };
-        Ok(grant)
+    pub fn stats(&self) -> Stats {
+        let capacity = self.capacity();
+        sync::critical(|| self.counters().snapshot(self.book(), capacity))
     }
 }
 
 impl<'a> Ring<'a> {
+    // A ring over caller-provided memory instead of a `Buffer`, for sizes that are only known at
+    // link time or regions like a dedicated ram bank. The bookkeeping takes up the first few
+    // words of `buf` and the rest is the ring's capacity. Whatever `buf` holds is overwritten.
+    #[inline(never)]
+    pub fn from_slice(buf: &'a mut [u8]) -> Result<Self, Error> {
+        let (header, slots) = carve::<Header>(buf).ok_or(Error::TooSmall)?;
+
+        unsafe {
+            header.write(Header::new());
+            Ok(Self::from_parts(&*header, slots))
+        }
+    }
+
     #[inline]
-    pub(crate) fn _dst(&self, _cs: CriticalSection) -> &mut Dst<[MaybeUninit<u8>]> {
-        unsafe { &mut *(self.dst) }
+    pub fn split_framed(&self) -> Result<(FrameProducer<'a>, FrameConsumer<'a>), Error> {
+        let (producer, consumer) = self.split()?;
+        Ok((FrameProducer { producer }, FrameConsumer { consumer }))
     }
 
+    // like `split_framed`, but the producer can be shared between any number of writers
+    #[inline(never)]
+    pub fn split_multi(&self) -> Result<(MultiProducer<'a>, FrameConsumer<'a>), Error> {
+        if sync::critical(|| self.flag_split()) {
+            return Err(Error::AlreadySplit);
+        }
+
+        let consumer = Consumer::new(self.alias());
+        Ok((MultiProducer::new(self.alias()), FrameConsumer { consumer }))
+    }
+}
+
+impl<T> Ring<'_, T> {
+    // another handle to the same buffer
     #[inline]
-    pub(crate) unsafe fn view(&self, range: Range<usize>) -> &[u8] {
-        unsafe {
-            let dst = &*(self.dst);
-            dst.buf.get_unchecked(range).assume_init_ref()
+    pub(crate) fn alias(&self) -> Self {
+        Self {
+            header: self.header,
+            slots: self.slots,
+            #[cfg(loom)]
+            shadows: self.shadows,
         }
     }
 
     #[inline]
-    pub(crate) unsafe fn view_mut(&self, range: Range<usize>) -> &mut [u8] {
-        unsafe {
-            let dst = &mut *(self.dst);
-            dst.buf.get_unchecked_mut(range).assume_init_mut()
+    pub(crate) fn book(&self) -> &Book {
+        &self.header.book
+    }
+
+    #[inline]
+    pub(crate) fn read_waker(&self) -> &AtomicWaker {
+        &self.header.read_waker
+    }
+
+    #[inline]
+    pub(crate) fn write_waker(&self) -> &AtomicWaker {
+        &self.header.write_waker
+    }
+
+    #[inline]
+    pub(crate) fn dropped_records(&self) -> &AtomicUsize {
+        &self.header.dropped_records
+    }
+
+    #[inline]
+    pub(crate) fn dropped_bytes(&self) -> &AtomicUsize {
+        &self.header.dropped_bytes
+    }
+
+    #[inline]
+    pub(crate) fn counters(&self) -> &Counters {
+        &self.header.counters
+    }
+
+    #[inline]
+    pub(crate) fn flag_split(&self) -> bool {
+        self.header.split.swap(true, Ordering::AcqRel)
+    }
+
+    #[inline]
+    pub(crate) fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    // Raw pointer to the elements in `range`. Turning it into a reference is up to the caller,
+    // which has to own the range through a grant for as long as the reference lives.
+    #[inline]
+    pub(crate) fn slots(&self, range: Range<usize>) -> *mut [MaybeUninit<T>] {
+        #[cfg(loom)]
+        for shadow in self.shadows.get(range.clone()).unwrap_or_default() {
+            shadow.with_mut(|_| ());
         }
+
+        self.ptr(range)
+    }
+
+    #[inline]
+    pub(crate) unsafe fn view(&self, range: Range<usize>) -> &[T] {
+        #[cfg(loom)]
+        for shadow in self.shadows.get(range.clone()).unwrap_or_default() {
+            shadow.with(|_| ());
+        }
+
+        unsafe { &*(self.ptr(range) as *const [T]) }
+    }
+
+    #[inline]
+    fn ptr(&self, range: Range<usize>) -> *mut [MaybeUninit<T>] {
+        _unsafe_assert!(range.start <= range.end && range.end <= self.slots.len());
+        let base = UnsafeCell::raw_get(self.slots.as_ptr());
+        ptr::slice_from_raw_parts_mut(unsafe { base.add(range.start) }, range.len())
     }
 }
 
-unsafe impl Send for Ring<'_> {}
-unsafe impl Sync for Ring<'_> {}
+impl<T> fmt::Debug for Ring<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ring")
+            .field("capacity", &self.capacity())
+            .finish_non_exhaustive()
+    }
+}
+
+// Splits caller-provided memory into a `P` at the first suitably aligned position and the byte
+// slots after it, as long as at least one slot is left. Whatever `buf` holds is kept, `P` is up to
+// the caller to initialize.
+pub(crate) fn carve<P>(buf: &mut [u8]) -> Option<(*mut P, &[Slot<u8>])> {
+    let offset = buf.as_ptr().align_offset(mem::align_of::<P>());
+    let reserved = offset.saturating_add(mem::size_of::<P>());
+
+    if buf.len() <= reserved {
+        return None;
+    }
+
+    let (head, slots) = buf[offset..].split_at_mut(reserved - offset);
+
+    // `Slot<u8>` has the layout of a byte and the bytes are already initialized
+    let slots = unsafe { &*(ptr::from_mut(slots) as *const [Slot<u8>]) };
+    Some((head.as_mut_ptr().cast(), slots))
+}
+
+unsafe impl<T: Send> Send for Ring<'_, T> {}
+unsafe impl<T: Send> Sync for Ring<'_, T> {}
+
+// a ring of `T` instead of bytes. grants hand out `MaybeUninit<T>` to fill and `T` to read.
+pub type TypedRing<'a, T> = Ring<'a, T>;
@@ -0,0 +1,79 @@
+// Incremental writes into a byte grant, for when the size isn't known up front.
+//
+// A cursor appends behind everything written so far and commits exactly that much when it's
+// finished. Anything that doesn't fit poisons the cursor: the rest of the writes fail and finishing
+// it hands the whole grant back instead of publishing a truncated record. Dropping a cursor does
+// the same.
+
+use core::fmt;
+
+use crate::Error;
+use crate::grant::GrantWrite;
+
+#[derive(Debug)]
+pub struct WriteCursor<'a> {
+    grant: GrantWrite<'a>,
+    len: usize,
+    overflowed: bool,
+}
+
+impl<'a> GrantWrite<'a> {
+    #[inline]
+    pub fn cursor(self) -> WriteCursor<'a> {
+        WriteCursor {
+            grant: self,
+            len: 0,
+            overflowed: false,
+        }
+    }
+}
+
+impl WriteCursor<'_> {
+    // fails without writing anything if `bytes` doesn't fit in what is left of the grant
+    #[inline]
+    pub fn append(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if self.overflowed || bytes.len() > self.remaining() {
+            self.overflowed = true;
+            return Err(Error::InsufficientSize);
+        }
+
+        let end = self.len + bytes.len();
+        self.grant.buf_mut()[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn written(&self) -> &[u8] {
+        &self.grant.buf()[..self.len]
+    }
+
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        self.grant.buf().len() - self.len
+    }
+
+    #[inline]
+    pub fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+
+    // commits everything written and returns its length, or releases the grant after an overflow
+    #[inline]
+    pub fn finish(self) -> Result<usize, Error> {
+        match self.overflowed {
+            true => Err(Error::InsufficientSize),
+            false => {
+                self.grant.commit(self.len);
+                Ok(self.len)
+            }
+        }
+    }
+}
+
+impl fmt::Write for WriteCursor<'_> {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.append(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
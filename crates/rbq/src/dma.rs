@@ -0,0 +1,122 @@
+// Grants for peripherals that move data in and out of the ring memory themselves.
+//
+// An aligned write grant starts at an address aligned to the transfer width and spans a whole
+// number of transfers. When the write index isn't aligned, the bytes up to the next aligned
+// address are padding: zeroed, and committed in front of the grant only when it commits anything.
+// If every write goes through aligned grants and commits whole transfers that only happens at the
+// wrap point, and not even there when the ring memory is aligned itself.
+//
+// So for a reader that releases whole transfers too, an unaligned read index always points at
+// padding. `read_aligned` releases it before handing out the data behind it, so its grants stay
+// aligned and never contain padding.
+//
+// `transfer` lends a grant's buffer to something like a dma transfer for as long as it runs and
+// commits what it reports to have moved. Dropping the transfer halfway commits nothing, the same
+// as dropping the grant.
+
+use core::ptr;
+
+use crate::grant::{GrantRead, GrantWrite};
+use crate::split::{Consumer, Producer};
+use crate::{Error, stats, sync};
+
+impl Producer<'_> {
+    // Like `grant_exact`, with the grant aligned to `align`, a power of two, and `size` rounded up
+    // to a multiple of it. Needs as much more room as there is padding in front of it.
+    #[inline(never)]
+    pub fn grant_exact_aligned(&self, size: usize, align: usize) -> Result<GrantWrite, Error> {
+        assert!(align.is_power_of_two());
+
+        let ring = &self.ring;
+        let capacity = ring.capacity();
+        let base = ring.slots(0..0).cast::<u8>().addr();
+        let size = size
+            .checked_next_multiple_of(align)
+            .ok_or(Error::TooLarge)?;
+
+        let (range, skipped) = sync::critical(|| {
+            let result = ring.book().acquire_write_skip(capacity, size, |start| {
+                (base + start).next_multiple_of(align) - (base + start)
+            });
+            stats::track(ring.counters(), stats::Side::Write, result)
+        })?;
+
+        let skipped = ring.slots(skipped);
+        unsafe { ptr::write_bytes(skipped.cast::<u8>(), 0, skipped.len()) };
+
+        let grant = GrantWrite { ring, range };
+        Ok(grant)
+    }
+}
+
+impl Consumer<'_> {
+    // Like `read`, cut down to a whole number of `align` sized transfers. Fails while there's less
+    // than one to read. Anything in front of the next aligned address is taken to be padding and
+    // released first, so this is only for a producer that writes through aligned grants.
+    #[inline(never)]
+    pub fn read_aligned(&self, align: usize) -> Result<GrantRead, Error> {
+        assert!(align.is_power_of_two());
+
+        let ring = &self.ring;
+        let base = ring.slots(0..0).cast::<u8>().addr();
+        let padding = |start: usize| (base + start).next_multiple_of(align) - (base + start);
+
+        let range = sync::critical(|| {
+            let book = ring.book();
+            let result = book.acquire_read().and_then(|range| {
+                let skip = padding(range.to_range().start).min(range.to_len());
+                if skip == 0 {
+                    return Ok(range);
+                }
+
+                // not counted as read, it wasn't counted as written either
+                book.commit_read(range.to_len(), skip);
+                book.acquire_read()
+            });
+
+            let result = result.and_then(|range| {
+                let transfers = range.to_len() & !(align - 1);
+
+                match transfers {
+                    0 => {
+                        book.release_read();
+                        Err(Error::InsufficientSize)
+                    }
+                    _ => Ok(range.truncate(transfers)),
+                }
+            });
+            stats::track(ring.counters(), stats::Side::Read, result)
+        })?;
+
+        let grant = GrantRead { ring, range };
+        Ok(grant)
+    }
+}
+
+impl GrantWrite<'_> {
+    // Hands the buffer to `f` to fill, and commits as many bytes as it returns, at most the whole
+    // grant. Commits nothing if it fails or is dropped before it's done.
+    pub async fn transfer<E>(
+        mut self,
+        f: impl AsyncFnOnce(&mut [u8]) -> Result<usize, E>,
+    ) -> Result<usize, E> {
+        let len = self.buf().len();
+        let used = f(self.buf_mut()).await?.min(len);
+        self.commit(used);
+        Ok(used)
+    }
+}
+
+impl GrantRead<'_> {
+    // Hands the buffer to `f` to send, and releases as many bytes as it returns, at most the
+    // whole grant. Releases nothing if it fails or is dropped before it's done.
+    pub async fn transfer<E>(
+        self,
+        f: impl AsyncFnOnce(&[u8]) -> Result<usize, E>,
+    ) -> Result<usize, E> {
+        let len = self.buf().len();
+        let used = f(self.buf()).await?.min(len);
+        self.commit(used);
+        Ok(used)
+    }
+}
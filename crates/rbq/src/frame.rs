@@ -0,0 +1,374 @@
+use crate::buffer::Ring;
+use crate::grant::{GrantRead, GrantWrite};
+use crate::split::{Consumer, Producer};
+use crate::sync::Ordering;
+use crate::wait::PollFn;
+use crate::{_unsafe_assert, Error, stats, sync};
+
+// Every record is prefixed with a LEB128 varint header holding its length shifted up by one bit.
+// The low bit marks padding: a record that only fills space and is skipped on the way out, which
+// only multi-producer rings write. The header width is fixed when the grant is handed out (from
+// the maximum size), so short records written into large grants are encoded with redundant
+// continuation bytes instead of being moved after the fact.
+//
+// Records are always granted contiguously, so a record never straddles the wrap point and the
+// consumer only ever sees whole records. Headers are still checked on the way out, since the bytes
+// of a persistent ring may not have survived a reset. A malformed one makes the rest of the
+// readable data untrustworthy, so all of it is dropped and counted in `Dropped`.
+//
+// Overwriting grants make room by discarding whole records from the head of the ring. The producer
+// only does this while the consumer holds no grant, so a record it is reading is never discarded;
+// the grant fails instead, like a regular one on a full ring. Either way a record is lost, and both
+// end up in `Dropped`. A consumer that reads while a record is being discarded finds nothing to
+// read and waits, it doesn't fail.
+
+const VARINT_MAX_LEN: usize = (usize::BITS as usize).div_ceil(7);
+
+#[inline]
+fn varint_len(value: usize) -> usize {
+    let bits = (usize::BITS - value.leading_zeros()).max(1) as usize;
+    bits.div_ceil(7)
+}
+
+#[inline]
+fn varint_encode(mut value: usize, out: &mut [u8]) {
+    let (last, rest) = out.split_last_mut().unwrap();
+
+    for byte in rest {
+        *byte = (value as u8 & 0x7f) | 0x80;
+        value >>= 7;
+    }
+
+    _unsafe_assert!(value <= 0x7f);
+    *last = value as u8;
+}
+
+// header width for records of up to `max` bytes
+#[inline]
+pub(crate) fn header_len(max: usize) -> usize {
+    varint_len(max.saturating_mul(2) | 1)
+}
+
+// the header of a record of `len` bytes, spread over all of `out`
+#[inline]
+pub(crate) fn header_encode(len: usize, padding: bool, out: &mut [u8]) {
+    varint_encode((len << 1) | padding as usize, out);
+}
+
+// a padding record spanning all of `out`, which can't be empty
+#[inline]
+pub(crate) fn padding_encode(out: &mut [u8]) {
+    let hdr_len = header_len(out.len());
+    _unsafe_assert!(hdr_len <= out.len());
+    let (hdr, rest) = out.split_at_mut(hdr_len);
+    header_encode(rest.len(), true, hdr);
+}
+
+// The record length, header length and padding flag of the record at the start of `buf`, if it
+// fits in `buf`.
+#[inline]
+fn header_decode(buf: &[u8]) -> Option<(usize, usize, bool)> {
+    let mut value = 0;
+
+    for (i, byte) in buf.iter().take(VARINT_MAX_LEN).enumerate() {
+        value |= ((byte & 0x7f) as usize) << (7 * i);
+
+        if byte & 0x80 == 0 {
+            let (len, hdr_len) = (value >> 1, i + 1);
+            return match len <= buf.len() - hdr_len {
+                true => Some((len, hdr_len, value & 1 == 1)),
+                false => None,
+            };
+        }
+    }
+
+    None
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Dropped {
+    pub records: usize,
+    pub bytes: usize,
+}
+
+impl Ring<'_> {
+    /// `Ring::drain_unchecked` for framed rings, one record at a time like `FrameConsumer::drain`.
+    /// Returns the number of records, malformed data is dropped.
+    ///
+    /// # Safety
+    ///
+    /// Same as `Ring::drain_unchecked`.
+    #[inline(never)]
+    pub unsafe fn drain_framed_unchecked(&self, mut f: impl FnMut(&[u8])) -> usize {
+        let mut drained = 0;
+
+        // records never straddle the wrap, so each half holds whole ones
+        let walk = |mut buf: &[u8]| {
+            while let Some((len, hdr_len, padding)) = header_decode(buf) {
+                if !padding {
+                    f(&buf[hdr_len..(hdr_len + len)]);
+                    drained += 1;
+                }
+                buf = &buf[(hdr_len + len)..];
+            }
+        };
+
+        unsafe { self.drain_unchecked(walk) };
+        drained
+    }
+}
+
+#[derive(Debug)]
+pub struct FrameProducer<'a> {
+    pub(crate) producer: Producer<'a>,
+}
+
+impl FrameProducer<'_> {
+    #[inline(never)]
+    pub fn grant(&self, max: usize) -> Result<FrameGrantWrite, Error> {
+        let hdr_len = header_len(max);
+        let grant = self.producer.grant_exact(hdr_len + max)?;
+        Ok(FrameGrantWrite { grant, hdr_len })
+    }
+
+    // `grant` without counting a failure, for callers that retry and count the outcome
+    #[cfg(feature = "time")]
+    #[inline]
+    pub(crate) fn grant_untracked(&self, max: usize) -> Result<FrameGrantWrite, Error> {
+        let hdr_len = header_len(max);
+        let grant = self
+            .producer
+            .grant_exact_untracked(hdr_len.saturating_add(max))?;
+        Ok(FrameGrantWrite { grant, hdr_len })
+    }
+
+    // Like `grant`, but discards the oldest records until the new one fits. If it can't be made
+    // to fit, the new record is the one counted as dropped.
+    #[inline(never)]
+    pub fn grant_overwrite(&self, max: usize) -> Result<FrameGrantWrite, Error> {
+        let ring = &self.producer.ring;
+        let hdr_len = header_len(max);
+        let size = hdr_len.saturating_add(max);
+
+        let result = match size > ring.capacity() {
+            // would never fit, not even in an empty ring
+            true => Err(Error::TooLarge),
+            false => loop {
+                match self.producer.grant_exact_untracked(size) {
+                    Err(Error::InsufficientSize) if self.discard_oldest() => {}
+                    result => break result,
+                }
+            },
+        };
+
+        // one grant as far as the stats go, however many tries it took
+        match sync::critical(|| stats::track(ring.counters(), stats::Side::Write, result)) {
+            Ok(grant) => Ok(FrameGrantWrite { grant, hdr_len }),
+            Err(err) => {
+                self.discard(max);
+                Err(err)
+            }
+        }
+    }
+
+    // counts a record of `len` bytes that never made it into the ring in `dropped`
+    #[inline]
+    pub fn discard(&self, len: usize) {
+        let ring = &self.producer.ring;
+
+        sync::critical(|| {
+            ring.dropped_records().fetch_add(1, Ordering::Relaxed);
+            ring.dropped_bytes().fetch_add(len, Ordering::Relaxed);
+        });
+    }
+
+    #[inline(never)]
+    fn discard_oldest(&self) -> bool {
+        let ring = &self.producer.ring;
+
+        let discarded = sync::critical(|| {
+            ring.book().try_discard(|range| {
+                let buf = unsafe { ring.view(range) };
+                let (used, len, padding) = match header_decode(buf) {
+                    Some((len, hdr_len, padding)) => (hdr_len + len, len, padding),
+                    None => (buf.len(), buf.len(), false),
+                };
+                ring.counters().on_read(used);
+
+                // padding makes room without losing anything
+                if !padding {
+                    ring.dropped_records().fetch_add(1, Ordering::Relaxed);
+                    ring.dropped_bytes().fetch_add(len, Ordering::Relaxed);
+                }
+                used
+            })
+        });
+
+        // a reader that came in meanwhile backed off, and the grant may still not happen
+        if discarded {
+            ring.read_waker().wake();
+        }
+        discarded
+    }
+
+    #[inline]
+    pub fn poll<'b, F, T>(&'b self, op: F) -> PollFn<'b, Self, F>
+    where
+        F: Fn(&'b Self) -> Option<T>,
+    {
+        PollFn::new(self, self.producer.ring.write_waker(), op)
+    }
+}
+
+#[derive(Debug)]
+pub struct FrameConsumer<'a> {
+    pub(crate) consumer: Consumer<'a>,
+}
+
+impl FrameConsumer<'_> {
+    // The next record, past any padding. Fails with `Error::Codec` if its header is malformed,
+    // after dropping everything readable from there on.
+    #[inline(never)]
+    pub fn read(&self) -> Result<FrameGrant, Error> {
+        loop {
+            if let Some(grant) = self.decode(self.consumer.read()?)? {
+                return Ok(grant);
+            }
+        }
+    }
+
+    // `read` without counting a failure, for callers that retry and count the outcome
+    #[cfg(feature = "time")]
+    #[inline]
+    pub(crate) fn read_untracked(&self) -> Result<FrameGrant, Error> {
+        loop {
+            if let Some(grant) = self.decode(self.consumer.read_untracked()?)? {
+                return Ok(grant);
+            }
+        }
+    }
+
+    // the record at the start of `grant`, or `None` after releasing padding
+    #[inline]
+    fn decode<'b>(&'b self, grant: GrantRead<'b>) -> Result<Option<FrameGrant<'b>>, Error> {
+        let Some((len, hdr_len, padding)) = header_decode(grant.buf()) else {
+            return Err(self.corrupted(grant));
+        };
+
+        if padding {
+            grant.commit(hdr_len + len);
+            return Ok(None);
+        }
+
+        Ok(Some(FrameGrant {
+            grant,
+            hdr_len,
+            len,
+        }))
+    }
+
+    #[cold]
+    fn corrupted(&self, grant: GrantRead) -> Error {
+        let ring = &self.consumer.ring;
+        let len = grant.buf().len();
+        grant.commit(len);
+
+        sync::critical(|| {
+            ring.dropped_records().fetch_add(1, Ordering::Relaxed);
+            ring.dropped_bytes().fetch_add(len, Ordering::Relaxed);
+        });
+
+        Error::Codec
+    }
+
+    // like `Consumer::drain`, one record at a time. returns the number of records, malformed ones
+    // are skipped.
+    #[inline(never)]
+    pub fn drain(&self, mut f: impl FnMut(&[u8])) -> Result<usize, Error> {
+        let mut drained = 0;
+
+        loop {
+            match self.read() {
+                Ok(grant) => {
+                    f(grant.buf());
+                    grant.commit();
+                    drained += 1;
+                }
+                Err(Error::Codec) => {}
+                Err(Error::InsufficientSize) => return Ok(drained),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    // totals of the records overwriting grants have discarded or failed to place so far, of the
+    // ones passed to `FrameProducer::discard` and of malformed data, wrapping on overflow
+    #[inline]
+    pub fn dropped(&self) -> Dropped {
+        let ring = &self.consumer.ring;
+
+        sync::critical(|| Dropped {
+            records: ring.dropped_records().load(Ordering::Relaxed),
+            bytes: ring.dropped_bytes().load(Ordering::Relaxed),
+        })
+    }
+
+    #[inline]
+    pub fn poll<'b, F, T>(&'b self, op: F) -> PollFn<'b, Self, F>
+    where
+        F: Fn(&'b Self) -> Option<T>,
+    {
+        PollFn::new(self, self.consumer.ring.read_waker(), op)
+    }
+}
+
+#[must_use]
+#[derive(Debug)]
+pub struct FrameGrantWrite<'a> {
+    grant: GrantWrite<'a>,
+    hdr_len: usize,
+}
+
+impl FrameGrantWrite<'_> {
+    #[inline]
+    pub fn buf(&self) -> &[u8] {
+        &self.grant.buf()[self.hdr_len..]
+    }
+
+    #[inline]
+    pub fn buf_mut(&mut self) -> &mut [u8] {
+        &mut self.grant.buf_mut()[self.hdr_len..]
+    }
+
+    // commits a record of `used` bytes, at most the whole grant. dropping the grant instead
+    // discards it.
+    #[inline]
+    pub fn commit(mut self, used: usize) {
+        let used = used.min(self.buf().len());
+        let hdr_len = self.hdr_len;
+        header_encode(used, false, &mut self.grant.buf_mut()[..hdr_len]);
+        self.grant.commit(hdr_len + used);
+    }
+}
+
+#[must_use]
+#[derive(Debug)]
+pub struct FrameGrant<'a> {
+    grant: GrantRead<'a>,
+    hdr_len: usize,
+    len: usize,
+}
+
+impl FrameGrant<'_> {
+    #[inline]
+    pub fn buf(&self) -> &[u8] {
+        &self.grant.buf()[self.hdr_len..(self.hdr_len + self.len)]
+    }
+
+    // releases the record. dropping the grant instead leaves it in the ring.
+    #[inline]
+    pub fn commit(self) {
+        self.grant.commit(self.hdr_len + self.len);
+    }
+}
@@ -1,31 +1,42 @@
-use core::mem;
-
-use critical_section::CriticalSection;
+use core::mem::{self, MaybeUninit};
+use core::ops::Range;
+use core::ptr;
 
 use crate::book::GrantRange;
 use crate::buffer::Ring;
+use crate::sync;
 
-enum Ref<'a, 'ring> {
-    Write(&'a mut GrantWrite<'ring>),
-    Read(&'a mut GrantRead<'ring>),
+enum Ref<'a, 'ring, T> {
+    Write(&'a mut GrantWrite<'ring, T>),
+    Read(&'a mut GrantRead<'ring, T>),
+    ReadSplit(&'a mut GrantReadSplit<'ring, T>),
 }
 
 #[inline(never)]
-fn drop_grant(ty: Ref) {
-    critical_section::with(|cs| match ty {
-        Ref::Write(grant) => grant.commit_internal(cs, 0),
-        Ref::Read(grant) => grant.commit_internal(cs, 0),
-    });
+fn drop_grant<T>(ty: Ref<T>) {
+    match ty {
+        Ref::Write(grant) => grant.commit_internal(0),
+        Ref::Read(grant) => grant.commit_internal(0),
+        Ref::ReadSplit(grant) => grant.commit_internal(0),
+    }
+}
+
+// Consumed elements are dropped in place right before their slots are handed back to the producer.
+#[inline]
+pub(crate) unsafe fn drop_elements<T>(ring: &Ring<T>, range: Range<usize>) {
+    if mem::needs_drop::<T>() {
+        unsafe { ptr::drop_in_place(ring.slots(range) as *mut [T]) };
+    }
 }
 
 #[must_use]
 #[derive(Debug)]
-pub struct GrantWrite<'a> {
-    pub(crate) ring: &'a Ring<'a>,
+pub struct GrantWrite<'a, T = u8> {
+    pub(crate) ring: &'a Ring<'a, T>,
     pub(crate) range: GrantRange,
 }
 
-impl<'a> GrantWrite<'a> {
+impl GrantWrite<'_> {
     #[inline]
     pub fn buf(&self) -> &[u8] {
         let range = self.range.to_range();
@@ -35,33 +46,58 @@ impl<'a> GrantWrite<'a> {
     #[inline]
     pub fn buf_mut(&mut self) -> &mut [u8] {
         let range = self.range.to_range();
-        unsafe { self.ring.view_mut(range) }
+        unsafe { &mut *(self.ring.slots(range) as *mut [u8]) }
+    }
+
+    // commits the first `used` bytes, at most the whole grant
+    #[inline]
+    pub fn commit(self, used: usize) {
+        // bytes are valid whatever the slots hold
+        unsafe { self.commit_unchecked(used) }
     }
+}
 
+impl<T> GrantWrite<'_, T> {
     #[inline]
-    pub fn commit(mut self, cs: CriticalSection, used: usize) {
-        self.commit_internal(cs, used);
+    pub fn buf_uninit(&mut self) -> &mut [MaybeUninit<T>] {
+        let range = self.range.to_range();
+        unsafe { &mut *self.ring.slots(range) }
+    }
+
+    /// Commits the first `used` elements, at most the whole grant.
+    ///
+    /// # Safety
+    ///
+    /// Those elements have to have been written through `buf_uninit`, since the consumer reads
+    /// them and drops them as `T`s.
+    #[inline]
+    pub unsafe fn commit_unchecked(mut self, used: usize) {
+        self.commit_internal(used);
         mem::forget(self);
     }
 
     #[inline(never)]
-    fn commit_internal(&mut self, cs: CriticalSection, used: usize) {
-        let dst = self.ring._dst(cs);
+    fn commit_internal(&mut self, used: usize) {
+        let book = self.ring.book();
+        let len = self.range.to_len();
+        let used = used.min(len);
 
         if used == 0 {
-            dst.book.release_write();
+            sync::critical(|| book.release_write());
             return;
         }
 
-        let capacity = dst.buf.len();
-        dst.book
-            .commit_write_exact(capacity, self.range.to_len(), used);
+        let capacity = self.ring.capacity();
+        sync::critical(|| {
+            book.commit_write_exact(capacity, len, used);
+            self.ring.counters().on_write(used, book);
+        });
 
-        dst.waker.wake();
+        self.ring.read_waker().wake();
     }
 }
 
-impl<'a> Drop for GrantWrite<'a> {
+impl<T> Drop for GrantWrite<'_, T> {
     #[inline]
     fn drop(&mut self) {
         drop_grant(Ref::Write(self));
@@ -70,42 +106,118 @@ impl<'a> Drop for GrantWrite<'a> {
 
 #[must_use]
 #[derive(Debug)]
-pub struct GrantRead<'a> {
-    pub(crate) ring: &'a Ring<'a>,
+pub struct GrantRead<'a, T = u8> {
+    pub(crate) ring: &'a Ring<'a, T>,
     pub(crate) range: GrantRange,
 }
 
-impl<'a> GrantRead<'a> {
+impl<T> GrantRead<'_, T> {
     #[inline]
-    pub fn buf(&self) -> &[u8] {
+    pub fn buf(&self) -> &[T] {
         let range = self.range.to_range();
         unsafe { self.ring.view(range) }
     }
 
+    // releases the first `used` elements, at most the whole grant
     #[inline]
-    pub fn commit(mut self, cs: CriticalSection, used: usize) {
-        self.commit_internal(cs, used);
+    pub fn commit(mut self, used: usize) {
+        self.commit_internal(used);
         mem::forget(self);
     }
 
     #[inline(never)]
-    fn commit_internal(&mut self, cs: CriticalSection, used: usize) {
-        let dst = self.ring._dst(cs);
+    fn commit_internal(&mut self, used: usize) {
+        let book = self.ring.book();
+        let len = self.range.to_len();
+        let used = used.min(len);
 
         if used == 0 {
-            dst.book.release_read();
+            sync::critical(|| book.release_read());
             return;
         }
 
-        dst.book.commit_read(self.range.to_len(), used);
+        let start = self.range.to_range().start;
+        unsafe { drop_elements(self.ring, start..(start + used)) };
+
+        sync::critical(|| {
+            book.commit_read(len, used);
+            self.ring.counters().on_read(used);
+        });
 
-        dst.waker.wake()
+        self.ring.write_waker().wake()
     }
 }
 
-impl<'a> Drop for GrantRead<'a> {
+impl<T> Drop for GrantRead<'_, T> {
     #[inline]
     fn drop(&mut self) {
         drop_grant(Ref::Read(self));
     }
 }
+
+#[must_use]
+#[derive(Debug)]
+pub struct GrantReadSplit<'a, T = u8> {
+    pub(crate) ring: &'a Ring<'a, T>,
+    pub(crate) tail: GrantRange,
+    pub(crate) head: Option<GrantRange>,
+}
+
+impl<T> GrantReadSplit<'_, T> {
+    // the first slice runs up to the end of the ring, the second one continues from the start
+    #[inline]
+    pub fn bufs(&self) -> (&[T], &[T]) {
+        let tail = unsafe { self.ring.view(self.tail.to_range()) };
+        let head = match self.head {
+            Some(head) => unsafe { self.ring.view(head.to_range()) },
+            None => &[],
+        };
+
+        (tail, head)
+    }
+
+    #[inline]
+    pub fn combined_len(&self) -> usize {
+        self.tail.to_len() + self.head.map_or(0, GrantRange::to_len)
+    }
+
+    // releases the first `used` elements, at most the whole grant
+    #[inline]
+    pub fn commit(mut self, used: usize) {
+        self.commit_internal(used);
+        mem::forget(self);
+    }
+
+    #[inline(never)]
+    fn commit_internal(&mut self, used: usize) {
+        let book = self.ring.book();
+        let tail = self.tail.to_len();
+        let size = self.combined_len();
+        let used = used.min(size);
+
+        if used == 0 {
+            sync::critical(|| book.release_read());
+            return;
+        }
+
+        let start = self.tail.to_range().start;
+        unsafe { drop_elements(self.ring, start..(start + used.min(tail))) };
+        if used > tail {
+            unsafe { drop_elements(self.ring, 0..(used - tail)) };
+        }
+
+        sync::critical(|| {
+            book.commit_read_split(tail, size, used);
+            self.ring.counters().on_read(used);
+        });
+
+        self.ring.write_waker().wake()
+    }
+}
+
+impl<T> Drop for GrantReadSplit<'_, T> {
+    #[inline]
+    fn drop(&mut self) {
+        drop_grant(Ref::ReadSplit(self));
+    }
+}
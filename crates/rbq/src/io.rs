@@ -0,0 +1,188 @@
+// `embedded-io` and `embedded-io-async` adapters for the ring endpoints.
+//
+// Writes copy as much as fits into a `grant_max_remaining` grant and reads drain a single read
+// grant. The blocking traits spin until the other side makes progress, which is only useful when
+// it runs in another context such as an interrupt, a DMA completion or the other core.
+
+use core::hint;
+
+use crate::Error;
+use crate::grant::{GrantRead, GrantWrite};
+use crate::split::{Consumer, Producer};
+
+impl embedded_io::Error for Error {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            Error::InsufficientSize => embedded_io::ErrorKind::OutOfMemory,
+            Error::TooLarge | Error::TooSmall => embedded_io::ErrorKind::InvalidInput,
+            Error::Codec => embedded_io::ErrorKind::InvalidData,
+            Error::AlreadySplit | Error::GrantInProgress | Error::Lagged => {
+                embedded_io::ErrorKind::Other
+            }
+        }
+    }
+}
+
+#[inline]
+fn copy_into(mut grant: GrantWrite, buf: &[u8]) -> usize {
+    let len = buf.len().min(grant.buf().len());
+    grant.buf_mut()[..len].copy_from_slice(&buf[..len]);
+    grant.commit(len);
+    len
+}
+
+#[inline]
+fn copy_from(grant: GrantRead, buf: &mut [u8]) -> usize {
+    let len = buf.len().min(grant.buf().len());
+    buf[..len].copy_from_slice(&grant.buf()[..len]);
+    grant.commit(len);
+    len
+}
+
+// Hands out the readable region without holding on to the read grant. Nothing but the consumer
+// moves the read index, so the bytes stay put until the matching `consume`.
+#[inline]
+fn fill<'a>(consumer: &'a Consumer, grant: GrantRead) -> &'a [u8] {
+    let range = grant.range.to_range();
+    drop(grant);
+    unsafe { consumer.ring.view(range) }
+}
+
+// `amt` comes from the caller, so it's clamped to what's actually there rather than trusted
+#[inline]
+fn consume(consumer: &Consumer, amt: usize) {
+    if amt == 0 {
+        return;
+    }
+
+    if let Ok(grant) = consumer.read() {
+        let len = amt.min(grant.buf().len());
+        grant.commit(len);
+    }
+}
+
+impl embedded_io::ErrorType for Producer<'_> {
+    type Error = Error;
+}
+
+impl embedded_io::Write for Producer<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            match self.grant_max_remaining() {
+                Ok(grant) => return Ok(copy_into(grant, buf)),
+                Err(Error::InsufficientSize) => hint::spin_loop(),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl embedded_io::WriteReady for Producer<'_> {
+    fn write_ready(&mut self) -> Result<bool, Error> {
+        match self.grant_max_remaining() {
+            Ok(_) => Ok(true),
+            Err(Error::InsufficientSize) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl embedded_io::ErrorType for Consumer<'_> {
+    type Error = Error;
+}
+
+impl embedded_io::Read for Consumer<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            match Consumer::read(self) {
+                Ok(grant) => return Ok(copy_from(grant, buf)),
+                Err(Error::InsufficientSize) => hint::spin_loop(),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl embedded_io::BufRead for Consumer<'_> {
+    fn fill_buf(&mut self) -> Result<&[u8], Error> {
+        loop {
+            match Consumer::read(self) {
+                Ok(grant) => return Ok(fill(self, grant)),
+                Err(Error::InsufficientSize) => hint::spin_loop(),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        consume(self, amt);
+    }
+}
+
+impl embedded_io::ReadReady for Consumer<'_> {
+    fn read_ready(&mut self) -> Result<bool, Error> {
+        match Consumer::read(self) {
+            Ok(_) => Ok(true),
+            Err(Error::InsufficientSize) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl embedded_io_async::Write for Producer<'_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let grant = self
+            .poll(|p| match p.grant_max_remaining() {
+                Err(Error::InsufficientSize) => None,
+                result => Some(result),
+            })
+            .await?;
+
+        Ok(copy_into(grant, buf))
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl embedded_io_async::Read for Consumer<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let grant = self.read_async().await?;
+        Ok(copy_from(grant, buf))
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl embedded_io_async::BufRead for Consumer<'_> {
+    async fn fill_buf(&mut self) -> Result<&[u8], Error> {
+        let grant = self.read_async().await?;
+        Ok(fill(self, grant))
+    }
+
+    fn consume(&mut self, amt: usize) {
+        consume(self, amt);
+    }
+}
@@ -1,21 +1,87 @@
 #![no_std]
-#![feature(maybe_uninit_uninit_array, maybe_uninit_slice)]
 
+use core::fmt;
+
+#[cfg(feature = "time")]
+mod blocking;
 mod book;
+mod broadcast;
 mod buffer;
+mod cursor;
+mod dma;
+mod frame;
 mod grant;
+#[cfg(feature = "embedded-io")]
+mod io;
+#[cfg(feature = "postcard")]
+mod message;
+mod multi;
+#[cfg(not(loom))]
+mod persist;
+mod split;
+mod stats;
+#[cfg(feature = "stream")]
+mod stream;
+mod sync;
 mod wait;
 
-pub use buffer::{Buffer, Ring};
-pub use grant::{GrantRead, GrantWrite};
+pub use broadcast::{
+    BroadcastConsumer, BroadcastGrantRead, BroadcastGrantWrite, BroadcastProducer, Readers, Slowest,
+};
+pub use buffer::{Buffer, Ring, TypedBuffer, TypedRing};
+pub use cursor::WriteCursor;
+pub use frame::{Dropped, FrameConsumer, FrameGrant, FrameGrantWrite, FrameProducer};
+pub use grant::{GrantRead, GrantReadSplit, GrantWrite};
+#[cfg(feature = "postcard")]
+pub use message::{MessageConsumer, MessageGrant, MessageProducer};
+pub use multi::{MultiGrantWrite, MultiProducer};
+#[cfg(not(loom))]
+pub use persist::Boot;
+pub use split::{Consumer, Producer};
+#[cfg(feature = "stats")]
+pub use stats::Stats;
+#[cfg(feature = "stream")]
+pub use stream::ReadStream;
 pub use wait::PollFn;
 
-#[derive(defmt::Format)]
+// `InsufficientSize`, `GrantInProgress` and `Lagged` are temporary, the same call can succeed
+// later. `TooLarge`, `TooSmall` and `AlreadySplit` never go away on their own, and `Codec` is down
+// to the message itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error {
+    AlreadySplit,
     GrantInProgress,
+    // not enough free space or data right now
     InsufficientSize,
+    // more than the ring could ever hold, like a grant larger than its capacity
+    TooLarge,
+    // memory handed to `Ring::from_slice` or `Ring::from_persistent` that doesn't leave room for
+    // a single element after the bookkeeping
+    TooSmall,
+    // a broadcast consumer was left behind and skipped ahead, see `Slowest::Lags`
+    Lagged,
+    // a message that doesn't serialize, or a record that doesn't deserialize into the type asked
+    // for, see `MessageConsumer`
+    Codec,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Error::AlreadySplit => "ring already split",
+            Error::GrantInProgress => "grant already in progress",
+            Error::InsufficientSize => "not enough space or data right now",
+            Error::TooLarge => "larger than the ring can ever hold",
+            Error::TooSmall => "memory too small for the ring's bookkeeping",
+            Error::Lagged => "consumer fell behind and skipped ahead",
+            Error::Codec => "message failed to serialize or deserialize",
+        })
+    }
 }
 
+impl core::error::Error for Error {}
+
 macro_rules! internal_unreachable {
     () => {{
         #[cfg(debug_assertions)]
@@ -46,4 +112,19 @@ macro_rules! internal_unsafe_assert {
     }};
 }
 
-use {internal_unreachable as _unreachable, internal_unsafe_assert as _unsafe_assert};
+// loom's primitives can't be created in const context, so the constructors that build them are
+// only `const` outside of loom
+macro_rules! internal_const_fn {
+    ($(#[$attr:meta])* $vis:vis const fn $($rest:tt)*) => {
+        #[cfg(not(loom))]
+        $(#[$attr])* $vis const fn $($rest)*
+
+        #[cfg(loom)]
+        $(#[$attr])* $vis fn $($rest)*
+    };
+}
+
+use {
+    internal_const_fn as _const_fn, internal_unreachable as _unreachable,
+    internal_unsafe_assert as _unsafe_assert,
+};
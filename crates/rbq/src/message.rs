@@ -0,0 +1,130 @@
+// Serde messages on top of framed rings, encoded with postcard. Compiled in with the `postcard`
+// feature.
+//
+// Every message is one record, serialized straight into a write grant of exactly its encoded
+// size and deserialized straight out of the read grant, so data like `&str` or `&[u8]` can be
+// borrowed from the ring instead of copied. A record has to decode into the type asked for with
+// nothing left over, anything else fails with `Error::Codec`.
+
+use postcard::experimental::serialized_size;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+use crate::buffer::Ring;
+use crate::frame::{FrameConsumer, FrameGrant, FrameProducer};
+
+#[derive(Debug)]
+pub struct MessageProducer<'a> {
+    frames: FrameProducer<'a>,
+}
+
+#[derive(Debug)]
+pub struct MessageConsumer<'a> {
+    frames: FrameConsumer<'a>,
+}
+
+impl<'a> Ring<'a> {
+    #[inline]
+    pub fn split_message(&self) -> Result<(MessageProducer<'a>, MessageConsumer<'a>), Error> {
+        let (frames, consumer) = self.split_framed()?;
+        Ok((
+            MessageProducer { frames },
+            MessageConsumer { frames: consumer },
+        ))
+    }
+}
+
+impl MessageProducer<'_> {
+    pub fn send<M: Serialize + ?Sized>(&self, msg: &M) -> Result<(), Error> {
+        let size = serialized_size(msg).map_err(|_| Error::Codec)?;
+        let mut grant = self.frames.grant(size)?;
+        let used = postcard::to_slice(msg, grant.buf_mut())
+            .map_err(|_| Error::Codec)?
+            .len();
+
+        grant.commit(used);
+        Ok(())
+    }
+
+    // waits until there's room for `msg`
+    pub async fn send_async<M: Serialize + ?Sized>(&self, msg: &M) -> Result<(), Error> {
+        let size = serialized_size(msg).map_err(|_| Error::Codec)?;
+        let mut grant = self
+            .frames
+            .poll(|p| match p.grant(size) {
+                Err(Error::InsufficientSize) => None,
+                result => Some(result),
+            })
+            .await?;
+        let used = postcard::to_slice(msg, grant.buf_mut())
+            .map_err(|_| Error::Codec)?
+            .len();
+
+        grant.commit(used);
+        Ok(())
+    }
+}
+
+impl MessageConsumer<'_> {
+    // The next message, for types that don't borrow from the ring. The record is released either
+    // way, so one that doesn't decode can't hold up the ones after it.
+    pub fn recv<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        let grant = self.read()?;
+        let result = grant.decode();
+        grant.commit();
+        result
+    }
+
+    pub async fn recv_async<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        let grant = self.read_async().await?;
+        let result = grant.decode();
+        grant.commit();
+        result
+    }
+
+    // the next record, still encoded, for decoding types that borrow from it
+    #[inline]
+    pub fn read(&self) -> Result<MessageGrant, Error> {
+        let frame = self.frames.read()?;
+        Ok(MessageGrant { frame })
+    }
+
+    pub async fn read_async(&self) -> Result<MessageGrant, Error> {
+        let frame = self
+            .frames
+            .poll(|c| match c.read() {
+                Err(Error::InsufficientSize) => None,
+                result => Some(result),
+            })
+            .await?;
+        Ok(MessageGrant { frame })
+    }
+}
+
+#[must_use]
+#[derive(Debug)]
+pub struct MessageGrant<'a> {
+    frame: FrameGrant<'a>,
+}
+
+impl MessageGrant<'_> {
+    // the encoded message
+    #[inline]
+    pub fn buf(&self) -> &[u8] {
+        self.frame.buf()
+    }
+
+    pub fn decode<'de, T: Deserialize<'de>>(&'de self) -> Result<T, Error> {
+        match postcard::take_from_bytes(self.frame.buf()) {
+            Ok((msg, [])) => Ok(msg),
+            _ => Err(Error::Codec),
+        }
+    }
+
+    // releases the record. dropping the grant instead leaves it in the ring.
+    #[inline]
+    pub fn commit(self) {
+        self.frame.commit();
+    }
+}
@@ -0,0 +1,140 @@
+// Multi-producer mode for framed rings.
+//
+// Any number of writers, in any interrupt priority or on the other core, can hold reservations at
+// the same time. The reservation bookkeeping always runs inside a real critical section, with or
+// without the `atomic` feature, since it isn't a single compare-and-swap. The reader is a regular
+// `FrameConsumer` and sees the writers as one producer whose records land in reservation order.
+//
+// Reservations are exact: committing publishes the whole grant, so a record shorter than its
+// reservation is followed by a padding record over the rest. A grant that is dropped while it is
+// still the newest reservation gives its space back, otherwise it is published as one padding
+// record so the reservations after it don't get stuck behind it. The consumer skips padding on
+// its own.
+
+use core::mem;
+
+use crate::book::GrantRange;
+use crate::buffer::Ring;
+use crate::frame::{header_encode, header_len, padding_encode};
+use crate::{Error, stats};
+
+#[derive(Debug)]
+pub struct MultiProducer<'a> {
+    ring: Ring<'a>,
+}
+
+impl<'a> MultiProducer<'a> {
+    pub(crate) fn new(ring: Ring<'a>) -> Self {
+        Self { ring }
+    }
+
+    // a record of up to `max` bytes, like `FrameProducer::grant`
+    #[inline(never)]
+    pub fn grant(&self, max: usize) -> Result<MultiGrantWrite, Error> {
+        let ring = &self.ring;
+        let capacity = ring.capacity();
+        let hdr_len = header_len(max);
+        let size = hdr_len.saturating_add(max);
+        let (range, prev) = critical_section::with(|_| {
+            let result = ring.book().acquire_write_multi(capacity, size);
+            stats::track(ring.counters(), stats::Side::Write, result)
+        })?;
+        let grant = MultiGrantWrite {
+            ring,
+            range,
+            prev,
+            hdr_len,
+        };
+        Ok(grant)
+    }
+}
+
+impl Clone for MultiProducer<'_> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self::new(self.ring.alias())
+    }
+}
+
+#[must_use]
+#[derive(Debug)]
+pub struct MultiGrantWrite<'a> {
+    ring: &'a Ring<'a>,
+    range: GrantRange,
+    // where the reservation before this one ended
+    prev: usize,
+    hdr_len: usize,
+}
+
+impl MultiGrantWrite<'_> {
+    #[inline]
+    pub fn buf(&self) -> &[u8] {
+        let range = self.range.to_range();
+        unsafe { &self.ring.view(range)[self.hdr_len..] }
+    }
+
+    #[inline]
+    pub fn buf_mut(&mut self) -> &mut [u8] {
+        let hdr_len = self.hdr_len;
+        &mut self.reservation()[hdr_len..]
+    }
+
+    // the whole reservation, header included
+    #[inline]
+    fn reservation(&mut self) -> &mut [u8] {
+        let range = self.range.to_range();
+        unsafe { &mut *(self.ring.slots(range) as *mut [u8]) }
+    }
+
+    // Commits a record of `used` bytes, at most the whole grant. Committing nothing is the same as
+    // dropping the grant.
+    #[inline]
+    pub fn commit(mut self, used: usize) {
+        let used = used.min(self.buf().len());
+        self.commit_internal(used);
+        mem::forget(self);
+    }
+
+    #[inline(never)]
+    fn commit_internal(&mut self, used: usize) {
+        let book = self.ring.book();
+        let capacity = self.ring.capacity();
+        let hdr_len = self.hdr_len;
+
+        if used > 0 {
+            let buf = self.reservation();
+            header_encode(used, false, &mut buf[..hdr_len]);
+
+            let rest = &mut buf[(hdr_len + used)..];
+            if !rest.is_empty() {
+                padding_encode(rest);
+            }
+        }
+
+        let published = critical_section::with(|_| {
+            let written = match used {
+                0 if book.unreserve_multi(self.range, self.prev) => 0,
+                0 => {
+                    padding_encode(self.reservation());
+                    self.range.to_len()
+                }
+                _ => self.range.to_len(),
+            };
+
+            let published = book.commit_write_multi(capacity);
+            self.ring.counters().on_write(written, book);
+            published
+        });
+
+        if published {
+            self.ring.read_waker().wake();
+        }
+    }
+}
+
+impl Drop for MultiGrantWrite<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        self.commit_internal(0);
+    }
+}
@@ -0,0 +1,119 @@
+// Rings that survive a warm reset.
+//
+// The memory behind a persistent ring starts with a seal, followed by the usual bookkeeping and
+// then the data. A matching seal means the memory holds a ring laid out the way this build lays it
+// out, so after a reset the indices the previous boot left behind can be picked up again, along
+// with everything it had committed but not read yet. Nothing is written at runtime beyond what the
+// ring writes anyway, so a reset in the middle of anything only loses the grants in progress.
+//
+// For this to work the memory has to be left alone by the startup code, in a no-init section.
+//
+// Only the indices are checked, not what they point at. Framed consumers check every record header
+// they read, so a record that was corrupted across the reset is dropped rather than trusted.
+
+use core::mem;
+
+use crate::Error;
+use crate::book::Book;
+use crate::buffer::{self, Header, Ring};
+
+// "rbq_"
+const MAGIC: usize = 0x7262_715f;
+// bumped whenever the bookkeeping or the framing changes in a way the size doesn't show
+const VERSION: usize = 2;
+
+#[repr(C)]
+struct Region {
+    seal: Seal,
+    header: Header,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+struct Seal {
+    magic: usize,
+    version: usize,
+    capacity: usize,
+    // the bookkeeping changes with the features the crate is built with
+    layout: usize,
+    // keeps a seal with a corrupted word from passing by chance
+    check: usize,
+}
+
+impl Seal {
+    fn new(capacity: usize) -> Self {
+        let mut seal = Self {
+            magic: MAGIC,
+            version: VERSION,
+            capacity,
+            layout: mem::size_of::<Header>(),
+            check: 0,
+        };
+
+        seal.check = seal.checksum();
+        seal
+    }
+
+    // fnv-1a over everything but the checksum itself
+    fn checksum(&self) -> usize {
+        let mut hash: u32 = 0x811c_9dc5;
+
+        for word in [self.magic, self.version, self.capacity, self.layout] {
+            for byte in word.to_le_bytes() {
+                hash = (hash ^ byte as u32).wrapping_mul(0x0100_0193);
+            }
+        }
+
+        hash as usize
+    }
+}
+
+// what `Ring::from_persistent` found in its memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Boot {
+    // nothing to pick up, the ring starts out empty
+    Cold,
+    // the ring from before the reset, with `pending` bytes it had not read yet
+    Warm { pending: usize },
+}
+
+impl<'a> Ring<'a> {
+    // Like `from_slice`, for memory that keeps its contents across a warm reset. If `buf` still
+    // holds a valid ring from before the reset, the new one picks up where that one left off and
+    // its consumer sees whatever was left unread first. Otherwise it starts out empty.
+    #[inline(never)]
+    pub fn from_persistent(buf: &'a mut [u8]) -> Result<(Self, Boot), Error> {
+        let (region, slots) = buffer::carve::<Region>(buf).ok_or(Error::TooSmall)?;
+        let capacity = slots.len();
+
+        unsafe {
+            let seal = &raw mut (*region).seal;
+            let header = &raw mut (*region).header;
+
+            let book = match seal.read_volatile() == Seal::new(capacity) {
+                true => Book::recover(&raw const (*header).book, capacity),
+                false => None,
+            };
+
+            let boot = match book {
+                Some(book) => {
+                    let (tail, head) = book.filled();
+                    let pending = tail.len() + head.len();
+                    header.write(Header {
+                        book,
+                        ..Header::new()
+                    });
+                    Boot::Warm { pending }
+                }
+                None => {
+                    seal.write_volatile(Seal::new(capacity));
+                    header.write(Header::new());
+                    Boot::Cold
+                }
+            };
+
+            Ok((Self::from_parts(&*header, slots), boot))
+        }
+    }
+}
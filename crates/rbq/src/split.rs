@@ -0,0 +1,210 @@
+use core::cell::Cell;
+use core::marker::PhantomData;
+
+use crate::buffer::Ring;
+use crate::grant::{GrantRead, GrantReadSplit, GrantWrite};
+use crate::wait::PollFn;
+use crate::{Error, stats, sync};
+
+// Only one producer and one consumer are ever handed out per buffer, and neither is `Sync`. Sharing
+// an endpoint between execution contexts therefore has to go through a lock the caller owns, so two
+// writers racing for the same ring is a compile error rather than a dropped grant.
+pub(crate) type NotSync = PhantomData<Cell<()>>;
+
+#[derive(Debug)]
+pub struct Producer<'a, T = u8> {
+    pub(crate) ring: Ring<'a, T>,
+    _not_sync: NotSync,
+}
+
+impl<'a, T> Producer<'a, T> {
+    pub(crate) fn new(ring: Ring<'a, T>) -> Self {
+        Self {
+            ring,
+            _not_sync: PhantomData,
+        }
+    }
+
+    #[inline(never)]
+    pub fn grant_exact(&self, size: usize) -> Result<GrantWrite<T>, Error> {
+        let ring = &self.ring;
+        let capacity = ring.capacity();
+        let range = sync::critical(|| {
+            let result = ring.book().acquire_write_exact(capacity, size);
+            stats::track(ring.counters(), stats::Side::Write, result)
+        })?;
+        let grant = GrantWrite { ring, range };
+        Ok(grant)
+    }
+
+    // `grant_exact` without counting a failure, for callers that retry and count the outcome
+    #[inline]
+    pub(crate) fn grant_exact_untracked(&self, size: usize) -> Result<GrantWrite<T>, Error> {
+        let ring = &self.ring;
+        let capacity = ring.capacity();
+        let range = sync::critical(|| ring.book().acquire_write_exact(capacity, size))?;
+        let grant = GrantWrite { ring, range };
+        Ok(grant)
+    }
+
+    #[inline(never)]
+    pub fn grant_max_remaining(&self) -> Result<GrantWrite<T>, Error> {
+        let ring = &self.ring;
+        let capacity = ring.capacity();
+        let range = sync::critical(|| {
+            let result = ring.book().acquire_write_remaining(capacity);
+            stats::track(ring.counters(), stats::Side::Write, result)
+        })?;
+        let grant = GrantWrite { ring, range };
+        Ok(grant)
+    }
+
+    #[inline]
+    pub fn poll<'b, F, R>(&'b self, op: F) -> PollFn<'b, Self, F>
+    where
+        F: Fn(&'b Self) -> Option<R>,
+    {
+        PollFn::new(self, self.ring.write_waker(), op)
+    }
+
+    // waits until `size` bytes are free. fails right away for grants that can never fit.
+    pub async fn grant_exact_async(&self, size: usize) -> Result<GrantWrite<T>, Error> {
+        self.poll(|p| match p.grant_exact(size) {
+            Err(Error::InsufficientSize) if size > 0 => None,
+            result => Some(result),
+        })
+        .await
+    }
+}
+
+#[derive(Debug)]
+pub struct Consumer<'a, T = u8> {
+    pub(crate) ring: Ring<'a, T>,
+    _not_sync: NotSync,
+}
+
+impl<'a, T> Consumer<'a, T> {
+    pub(crate) fn new(ring: Ring<'a, T>) -> Self {
+        Self {
+            ring,
+            _not_sync: PhantomData,
+        }
+    }
+
+    #[inline(never)]
+    pub fn read(&self) -> Result<GrantRead<T>, Error> {
+        let ring = &self.ring;
+        let range = sync::critical(|| {
+            let result = ring.book().acquire_read();
+            stats::track(ring.counters(), stats::Side::Read, result)
+        })?;
+        let grant = GrantRead { ring, range };
+        Ok(grant)
+    }
+
+    // `read` without counting a failure, for callers that retry and count the outcome
+    #[cfg(feature = "time")]
+    #[inline]
+    pub(crate) fn read_untracked(&self) -> Result<GrantRead<T>, Error> {
+        let ring = &self.ring;
+        let range = sync::critical(|| ring.book().acquire_read())?;
+        let grant = GrantRead { ring, range };
+        Ok(grant)
+    }
+
+    #[inline(never)]
+    pub fn split_read(&self) -> Result<GrantReadSplit<T>, Error> {
+        let ring = &self.ring;
+        let (tail, head) = sync::critical(|| {
+            let result = ring.book().acquire_read_split();
+            stats::track(ring.counters(), stats::Side::Read, result)
+        })?;
+        let grant = GrantReadSplit { ring, tail, head };
+        Ok(grant)
+    }
+
+    #[inline(never)]
+    fn read_min(&self, n: usize) -> Result<GrantReadSplit<T>, Error> {
+        let ring = &self.ring;
+        let capacity = ring.capacity();
+        let (tail, head) = sync::critical(|| {
+            let result = ring.book().acquire_read_min(capacity, n);
+            stats::track(ring.counters(), stats::Side::Read, result)
+        })?;
+        let grant = GrantReadSplit { ring, tail, head };
+        Ok(grant)
+    }
+
+    // Exactly the next `n` elements, across the wrap if need be. Like any read grant it releases
+    // nothing unless it's committed.
+    #[inline]
+    pub fn peek(&self, n: usize) -> Result<GrantReadSplit<T>, Error> {
+        let mut grant = self.read_min(n)?;
+        let tail = grant.tail.to_len();
+
+        match grant.head {
+            Some(head) if n > tail => grant.head = Some(head.truncate(n - tail)),
+            _ => (grant.tail, grant.head) = (grant.tail.truncate(n), None),
+        }
+
+        Ok(grant)
+    }
+
+    // releases the next `n` elements without looking at them, or nothing if there aren't as many
+    #[inline]
+    pub fn skip(&self, n: usize) -> Result<(), Error> {
+        if n > 0 {
+            self.peek(n)?.commit(n);
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn poll<'b, F, R>(&'b self, op: F) -> PollFn<'b, Self, F>
+    where
+        F: Fn(&'b Self) -> Option<R>,
+    {
+        PollFn::new(self, self.ring.read_waker(), op)
+    }
+
+    // waits until there is data to read
+    pub async fn read_async(&self) -> Result<GrantRead<T>, Error> {
+        self.poll(|c| match c.read() {
+            Err(Error::InsufficientSize) => None,
+            result => Some(result),
+        })
+        .await
+    }
+
+    // waits until at least `n` elements can be read and hands out all of them
+    pub async fn read_at_least(&self, n: usize) -> Result<GrantReadSplit<T>, Error> {
+        self.poll(|c| match c.read_min(n) {
+            Err(Error::InsufficientSize) if n > 0 => None,
+            result => Some(result),
+        })
+        .await
+    }
+
+    // Hands everything committed to `f` and releases it, until the ring is empty. Returns how many
+    // elements that was. For flushing synchronously where nothing else runs anymore, like a panic
+    // handler. Fails with `GrantInProgress` if the consumer was stopped holding a grant, see
+    // `Ring::drain_unchecked` for that.
+    #[inline(never)]
+    pub fn drain(&self, mut f: impl FnMut(&[T])) -> Result<usize, Error> {
+        let mut drained = 0;
+
+        loop {
+            match self.read() {
+                Ok(grant) => {
+                    let len = grant.buf().len();
+                    f(grant.buf());
+                    grant.commit(len);
+                    drained += len;
+                }
+                Err(Error::InsufficientSize) => return Ok(drained),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
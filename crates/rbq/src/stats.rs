@@ -0,0 +1,188 @@
+// Occupancy and failure counters, compiled in with the `stats` feature.
+//
+// Every grant method reports its outcome here. Without the feature `Counters` is zero-sized and
+// all of the hooks are empty, so nothing is left of them after inlining.
+
+#[cfg(feature = "stats")]
+pub(crate) use self::imp::Counters;
+#[cfg(not(feature = "stats"))]
+pub(crate) use self::noop::Counters;
+use crate::Error;
+
+// A snapshot of the ring. Sizes are in elements, bytes for byte rings including framing headers.
+// The totals wrap on overflow.
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Stats {
+    pub capacity: usize,
+    // committed by the producer and not yet released by the consumer
+    pub used: usize,
+    // highest `used` seen right after a write commit
+    pub high_water: usize,
+    pub written: usize,
+    pub read: usize,
+    // write grants that failed for lack of room
+    pub full: usize,
+    // read grants that failed with nothing to read
+    pub empty: usize,
+    // failed grants, on either side
+    pub too_large: usize,
+    pub grant_in_progress: usize,
+}
+
+#[cfg(feature = "stats")]
+mod imp {
+    use super::{Side, Stats};
+    use crate::book::Book;
+    use crate::sync::{AtomicBool, AtomicUsize, Ordering};
+    use crate::{_const_fn, Error};
+
+    #[derive(Debug)]
+    pub(crate) struct Counters {
+        pub(super) high_water: AtomicUsize,
+        pub(super) written: AtomicUsize,
+        pub(super) read: AtomicUsize,
+        pub(super) full: AtomicUsize,
+        pub(super) empty: AtomicUsize,
+        pub(super) too_large: AtomicUsize,
+        pub(super) grant_in_progress: AtomicUsize,
+        // `used` for broadcast rings, whose bookkeeping isn't in the book. set once they're split.
+        pub(super) external: AtomicBool,
+        pub(super) fill: AtomicUsize,
+    }
+
+    #[inline]
+    fn fill(book: &Book) -> usize {
+        let (tail, head) = book.filled();
+        tail.len() + head.len()
+    }
+
+    impl Counters {
+        _const_fn! {
+            pub(crate) const fn new() -> Self {
+                Self {
+                    high_water: AtomicUsize::new(0),
+                    written: AtomicUsize::new(0),
+                    read: AtomicUsize::new(0),
+                    full: AtomicUsize::new(0),
+                    empty: AtomicUsize::new(0),
+                    too_large: AtomicUsize::new(0),
+                    grant_in_progress: AtomicUsize::new(0),
+                    external: AtomicBool::new(false),
+                    fill: AtomicUsize::new(0),
+                }
+            }
+        }
+
+        pub(crate) fn snapshot(&self, book: &Book, capacity: usize) -> Stats {
+            Stats {
+                capacity,
+                used: match self.external.load(Ordering::Relaxed) {
+                    true => self.fill.load(Ordering::Relaxed),
+                    false => fill(book),
+                },
+                high_water: self.high_water.load(Ordering::Relaxed),
+                written: self.written.load(Ordering::Relaxed),
+                read: self.read.load(Ordering::Relaxed),
+                full: self.full.load(Ordering::Relaxed),
+                empty: self.empty.load(Ordering::Relaxed),
+                too_large: self.too_large.load(Ordering::Relaxed),
+                grant_in_progress: self.grant_in_progress.load(Ordering::Relaxed),
+            }
+        }
+
+        #[inline]
+        pub(crate) fn on_write(&self, used: usize, book: &Book) {
+            self.written.fetch_add(used, Ordering::Relaxed);
+            self.high_water.fetch_max(fill(book), Ordering::Relaxed);
+        }
+
+        // for rings that keep track of what's filled themselves, from then on
+        #[inline]
+        pub(crate) fn on_fill(&self, fill: usize) {
+            self.external.store(true, Ordering::Relaxed);
+            self.fill.store(fill, Ordering::Relaxed);
+        }
+
+        // `on_write` for those rings
+        #[inline]
+        pub(crate) fn on_write_filled(&self, used: usize, fill: usize) {
+            self.written.fetch_add(used, Ordering::Relaxed);
+            self.high_water.fetch_max(fill, Ordering::Relaxed);
+            self.on_fill(fill);
+        }
+
+        #[inline]
+        pub(crate) fn on_read(&self, used: usize) {
+            self.read.fetch_add(used, Ordering::Relaxed);
+        }
+
+        #[inline]
+        pub(crate) fn on_error(&self, side: Side, err: &Error) {
+            let counter = match err {
+                Error::InsufficientSize => match side {
+                    Side::Write => &self.full,
+                    Side::Read => &self.empty,
+                },
+                Error::TooLarge => &self.too_large,
+                Error::GrantInProgress => &self.grant_in_progress,
+                Error::AlreadySplit | Error::TooSmall | Error::Lagged | Error::Codec => return,
+            };
+
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(not(feature = "stats"))]
+mod noop {
+    use super::Side;
+    use crate::Error;
+    use crate::book::Book;
+
+    #[derive(Debug)]
+    pub(crate) struct Counters;
+
+    impl Counters {
+        pub(crate) const fn new() -> Self {
+            Self
+        }
+
+        #[inline(always)]
+        pub(crate) fn on_write(&self, _used: usize, _book: &Book) {}
+
+        #[inline(always)]
+        pub(crate) fn on_fill(&self, _fill: usize) {}
+
+        #[inline(always)]
+        pub(crate) fn on_write_filled(&self, _used: usize, _fill: usize) {}
+
+        #[inline(always)]
+        pub(crate) fn on_read(&self, _used: usize) {}
+
+        #[inline(always)]
+        pub(crate) fn on_error(&self, _side: Side, _err: &Error) {}
+    }
+}
+
+// which end a grant was for, a full ring and an empty one are counted apart
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Side {
+    Write,
+    Read,
+}
+
+// passes `result` through, counting it if it's a failure
+#[inline(always)]
+pub(crate) fn track<T>(
+    counters: &Counters,
+    side: Side,
+    result: Result<T, Error>,
+) -> Result<T, Error> {
+    if let Err(err) = &result {
+        counters.on_error(side, err);
+    }
+
+    result
+}
@@ -0,0 +1,66 @@
+// `futures_core::Stream` over the read side, compiled in with the `stream` feature.
+//
+// Each item is the next read grant, taken the moment the stream is polled. Nothing is held in
+// between polls, so dropping the stream or a future waiting on it never loses data: a grant only
+// exists once it has been handed out. The stream never ends. Its only error is `GrantInProgress`,
+// when the previous item is still alive.
+
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use futures_core::Stream;
+
+use crate::Error;
+use crate::frame::{FrameConsumer, FrameGrant};
+use crate::grant::GrantRead;
+use crate::split::Consumer;
+
+#[derive(Debug)]
+pub struct ReadStream<'a, H> {
+    handle: &'a H,
+}
+
+impl<T> Consumer<'_, T> {
+    #[inline]
+    pub fn stream(&self) -> ReadStream<Self> {
+        ReadStream { handle: self }
+    }
+}
+
+impl FrameConsumer<'_> {
+    #[inline]
+    pub fn stream(&self) -> ReadStream<Self> {
+        ReadStream { handle: self }
+    }
+}
+
+// like `PollFn`, the waker goes in before trying so a commit landing in between can't be missed
+#[inline]
+fn next<R>(read: impl FnOnce() -> Result<R, Error>) -> Poll<Option<Result<R, Error>>> {
+    match read() {
+        Err(Error::InsufficientSize) => Poll::Pending,
+        result => Poll::Ready(Some(result)),
+    }
+}
+
+impl<'a, T> Stream for ReadStream<'a, Consumer<'_, T>> {
+    type Item = Result<GrantRead<'a, T>, Error>;
+
+    #[inline(never)]
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let consumer = self.handle;
+        consumer.ring.read_waker().register(cx.waker());
+        next(|| consumer.read())
+    }
+}
+
+impl<'a> Stream for ReadStream<'a, FrameConsumer<'_>> {
+    type Item = Result<FrameGrant<'a>, Error>;
+
+    #[inline(never)]
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let consumer = self.handle;
+        consumer.consumer.ring.read_waker().register(cx.waker());
+        next(|| consumer.read())
+    }
+}
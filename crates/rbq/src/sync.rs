@@ -0,0 +1,131 @@
+// Synchronization primitives backing the ring bookkeeping.
+//
+// With the `atomic` feature the indices are real atomics and the book is lock-free, in the style
+// of bbqueue. This needs compare-and-swap, which not every target has.
+//
+// Without it, the same API is provided by plain cells that must only be touched from within
+// `critical`, which masks interrupts for the duration of the operation.
+//
+// The wakers follow the same split: embassy's takes a critical section to swap the waker, so the
+// atomic backend uses the CAS-based one from `atomic-waker` instead.
+//
+// Building with `--cfg loom` swaps the atomics and wakers for loom's, so the tests in
+// `tests/loom.rs` can explore the interleavings. Loom always checks the lock-free book, with or
+// without the feature, since that is the one with interleavings to explore.
+
+pub(crate) use core::sync::atomic::Ordering;
+#[cfg(not(loom))]
+pub(crate) use core::sync::atomic::fence;
+#[cfg(all(feature = "atomic", not(loom)))]
+pub(crate) use core::sync::atomic::{AtomicBool, AtomicUsize};
+
+#[cfg(all(feature = "atomic", not(loom)))]
+pub(crate) use atomic_waker::AtomicWaker;
+#[cfg(not(any(feature = "atomic", loom)))]
+pub(crate) use embassy_sync::waitqueue::AtomicWaker;
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{AtomicBool, AtomicUsize, fence};
+
+#[cfg(not(any(feature = "atomic", loom)))]
+pub(crate) use self::cs::{AtomicBool, AtomicUsize};
+#[cfg(loom)]
+pub(crate) use self::model::AtomicWaker;
+
+#[cfg(any(feature = "atomic", loom))]
+#[inline(always)]
+pub(crate) fn critical<R>(f: impl FnOnce() -> R) -> R {
+    f()
+}
+
+#[cfg(not(any(feature = "atomic", loom)))]
+#[inline(always)]
+pub(crate) fn critical<R>(f: impl FnOnce() -> R) -> R {
+    critical_section::with(|_| f())
+}
+
+#[cfg(not(any(feature = "atomic", loom)))]
+mod cs {
+    use core::cell::Cell;
+
+    use super::Ordering;
+
+    // same layout as the integer, like the real thing
+    #[derive(Debug)]
+    #[repr(transparent)]
+    pub(crate) struct AtomicUsize(Cell<usize>);
+
+    impl AtomicUsize {
+        #[inline(always)]
+        pub(crate) const fn new(v: usize) -> Self {
+            Self(Cell::new(v))
+        }
+
+        #[inline(always)]
+        pub(crate) fn load(&self, _: Ordering) -> usize {
+            self.0.get()
+        }
+
+        #[inline(always)]
+        pub(crate) fn store(&self, v: usize, _: Ordering) {
+            self.0.set(v)
+        }
+
+        #[inline(always)]
+        pub(crate) fn fetch_add(&self, v: usize, _: Ordering) -> usize {
+            self.0.replace(self.0.get().wrapping_add(v))
+        }
+
+        #[cfg(feature = "stats")]
+        #[inline(always)]
+        pub(crate) fn fetch_max(&self, v: usize, _: Ordering) -> usize {
+            self.0.replace(self.0.get().max(v))
+        }
+    }
+
+    #[derive(Debug)]
+    pub(crate) struct AtomicBool(Cell<bool>);
+
+    impl AtomicBool {
+        #[inline(always)]
+        pub(crate) const fn new(v: bool) -> Self {
+            Self(Cell::new(v))
+        }
+
+        #[inline(always)]
+        pub(crate) fn load(&self, _: Ordering) -> bool {
+            self.0.get()
+        }
+
+        #[inline(always)]
+        pub(crate) fn store(&self, v: bool, _: Ordering) {
+            self.0.set(v)
+        }
+
+        #[inline(always)]
+        pub(crate) fn swap(&self, v: bool, _: Ordering) -> bool {
+            self.0.replace(v)
+        }
+    }
+}
+
+#[cfg(loom)]
+mod model {
+    use core::task::Waker;
+
+    // the subset of embassy's `AtomicWaker` the ring uses
+    pub(crate) struct AtomicWaker(loom::future::AtomicWaker);
+
+    impl AtomicWaker {
+        pub(crate) fn new() -> Self {
+            Self(loom::future::AtomicWaker::new())
+        }
+
+        pub(crate) fn register(&self, waker: &Waker) {
+            self.0.register_by_ref(waker);
+        }
+
+        pub(crate) fn wake(&self) {
+            self.0.wake();
+        }
+    }
+}
@@ -1,41 +1,37 @@
 use core::pin::{Pin, pin};
 use core::task::{Context, Poll};
 
-use critical_section::CriticalSection;
+use crate::sync::AtomicWaker;
 
-use crate::buffer::Ring;
-
-pub struct PollFn<'a, F> {
-    ring: &'a Ring<'a>,
+pub struct PollFn<'a, H, F> {
+    handle: &'a H,
+    waker: &'a AtomicWaker,
     op: F,
 }
 
-impl<'a, T, F> Future for PollFn<'a, F>
+impl<'a, H, F> PollFn<'a, H, F> {
+    #[inline]
+    pub(crate) fn new(handle: &'a H, waker: &'a AtomicWaker, op: F) -> Self {
+        Self { handle, waker, op }
+    }
+}
+
+impl<'a, H, T, F> Future for PollFn<'a, H, F>
 where
-    F: Fn(&'a Ring, CriticalSection) -> Option<T>,
+    F: Fn(&'a H) -> Option<T>,
 {
     type Output = T;
 
     #[inline(never)]
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        critical_section::with(|cs| {
-            let fut = pin!(self);
-            if let Some(result) = (fut.op)(fut.ring, cs) {
-                return Poll::Ready(result);
-            }
+        let fut = pin!(self);
 
-            fut.ring._dst(cs).waker.register(cx.waker());
-            Poll::Pending
-        })
-    }
-}
+        // register before trying so a commit landing in between can't be missed
+        fut.waker.register(cx.waker());
 
-impl<'a> Ring<'a> {
-    #[inline]
-    pub fn poll<'b, F, T>(&'b self, op: F) -> PollFn<'b, F>
-    where
-        F: Fn(&'b Ring, CriticalSection) -> Option<T>,
-    {
-        PollFn { ring: self, op }
+        match (fut.op)(fut.handle) {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
     }
 }
@@ -0,0 +1,155 @@
+// The synchronous APIs: the `time` feature's deadline-bound grants and the drain helpers.
+
+use std::mem;
+
+use rbq::{Buffer, Error, Ring};
+
+#[cfg(feature = "time")]
+#[test]
+fn gives_up_at_the_deadline() {
+    use embassy_time::{Duration, Instant};
+
+    let buffer = Buffer::<8>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split().unwrap();
+
+    let start = Instant::now();
+    let deadline = start + Duration::from_millis(20);
+    assert!(matches!(
+        consumer.read_blocking(deadline),
+        Err(Error::InsufficientSize)
+    ));
+    assert!(Instant::now() >= deadline);
+
+    producer.grant_exact(6).unwrap().commit(6);
+    assert!(matches!(
+        producer.grant_exact_blocking(4, deadline),
+        Err(Error::InsufficientSize)
+    ));
+
+    // never waits for what can't ever succeed
+    let deadline = Instant::now() + Duration::from_secs(60);
+    assert!(matches!(
+        producer.grant_exact_blocking(9, deadline),
+        Err(Error::TooLarge)
+    ));
+    assert!(matches!(
+        producer.grant_exact_blocking(0, deadline),
+        Err(Error::InsufficientSize)
+    ));
+}
+
+#[cfg(all(feature = "time", feature = "stats"))]
+#[test]
+fn counts_a_timeout_once() {
+    use embassy_time::{Duration, Instant};
+
+    let buffer = Buffer::<8>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split().unwrap();
+    let deadline = Instant::now() + Duration::from_millis(5);
+
+    assert!(consumer.read_blocking(deadline).is_err());
+    producer.grant_exact(6).unwrap().commit(6);
+    assert!(producer.grant_exact_blocking(4, deadline).is_err());
+
+    let stats = ring.stats();
+    assert_eq!((stats.full, stats.empty), (1, 1));
+}
+
+#[cfg(feature = "time")]
+#[test]
+fn waits_for_the_other_side() {
+    use std::thread;
+
+    use embassy_time::{Duration, Instant};
+
+    static BUFFER: Buffer<64> = Buffer::new();
+    let ring = Ring::new(&BUFFER);
+    let (producer, consumer) = ring.split_framed().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(60);
+
+    let reader = thread::spawn(move || {
+        for i in 0..1000u32 {
+            let grant = consumer.read_blocking(deadline).unwrap();
+            assert_eq!(grant.buf(), i.to_le_bytes());
+            grant.commit();
+        }
+    });
+
+    for i in 0..1000u32 {
+        let mut grant = producer.grant_blocking(4, deadline).unwrap();
+        grant.buf_mut().copy_from_slice(&i.to_le_bytes());
+        grant.commit(4);
+    }
+
+    reader.join().unwrap();
+}
+
+#[test]
+fn drains_everything_committed() {
+    let buffer = Buffer::<8>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split().unwrap();
+
+    // leave the data straddling the end of the ring
+    producer.grant_exact(6).unwrap().commit(6);
+    consumer.read().unwrap().commit(6);
+    let mut grant = producer.grant_exact(2).unwrap();
+    grant.buf_mut().copy_from_slice(b"ab");
+    grant.commit(2);
+    let mut grant = producer.grant_exact(4).unwrap();
+    grant.buf_mut().copy_from_slice(b"cdef");
+    grant.commit(4);
+
+    let mut out = Vec::new();
+    assert_eq!(consumer.drain(|buf| out.extend_from_slice(buf)), Ok(6));
+    assert_eq!(out, b"abcdef");
+    assert_eq!(consumer.drain(|_| unreachable!()), Ok(0));
+
+    // a read grant that is still held stays put
+    producer.grant_exact(1).unwrap().commit(1);
+    let held = consumer.read().unwrap();
+    assert_eq!(consumer.drain(|_| {}), Err(Error::GrantInProgress));
+    drop(held);
+}
+
+#[test]
+fn drains_whole_records() {
+    let buffer = Buffer::<32>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split_framed().unwrap();
+
+    for record in [&b"one"[..], b"", b"three"] {
+        let mut grant = producer.grant(record.len()).unwrap();
+        grant.buf_mut().copy_from_slice(record);
+        grant.commit(record.len());
+    }
+
+    let mut records = Vec::new();
+    assert_eq!(consumer.drain(|buf| records.push(buf.to_vec())), Ok(3));
+    assert_eq!(records, [&b"one"[..], b"", b"three"]);
+}
+
+#[test]
+fn drains_past_a_grant_that_is_never_finished() {
+    let buffer = Buffer::<32>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split_framed().unwrap();
+
+    for record in [&b"one"[..], b"two"] {
+        let mut grant = producer.grant(record.len()).unwrap();
+        grant.buf_mut().copy_from_slice(record);
+        grant.commit(record.len());
+    }
+
+    // as if the consumer's task was stopped by a panic halfway through a read
+    mem::forget(consumer.read().unwrap());
+    assert_eq!(consumer.drain(|_| {}), Err(Error::GrantInProgress));
+
+    let mut records = Vec::new();
+    let drained = unsafe { ring.drain_framed_unchecked(|buf| records.push(buf.to_vec())) };
+    assert_eq!(drained, 2);
+    assert_eq!(records, [b"one", b"two"]);
+    assert_eq!(unsafe { ring.drain_unchecked(|_| unreachable!()) }, 0);
+}
@@ -0,0 +1,36 @@
+// Random operation sequences on rings of every size in `model::SIZES`, checked against the
+// reference model. Set `RBQ_SEED` to replay a failing run.
+
+mod common;
+mod model;
+
+use common::Rng;
+
+const RUNS: usize = if cfg!(miri) { 16 } else { 4096 };
+const MAX_OPS: usize = 256;
+
+#[test]
+fn matches_model() {
+    let mut rng = Rng::from_env(0x5ee_d0f4_b00c);
+
+    for run in 0..RUNS {
+        let len = 1 + 3 * (rng.next() as usize % MAX_OPS);
+        let data: Vec<u8> = (0..len).map(|_| rng.next() as u8).collect();
+        let (capacity, ops) = model::decode(&data);
+
+        let result = std::panic::catch_unwind(|| model::check(&data));
+        if let Err(panic) = result {
+            eprintln!("run {run}, capacity {capacity}: {ops:?}");
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+#[test]
+fn every_size_fills_up() {
+    for (i, &capacity) in model::SIZES.iter().enumerate() {
+        // one exact grant of the whole ring, read back in one go
+        let data = [i as u8, 0, capacity as u8, capacity as u8, 3, 255, 0];
+        model::check(&data);
+    }
+}
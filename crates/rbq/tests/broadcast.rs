@@ -0,0 +1,254 @@
+// Random operation sequences on broadcast rings, checking that every consumer sees the byte stream
+// in order and, unless it was left behind, in full. Set `RBQ_SEED` to replay a failing run.
+
+mod common;
+
+use common::Rng;
+use rbq::{Buffer, Error, Readers, Ring, Slowest};
+
+const RUNS: usize = if cfg!(miri) { 8 } else { 512 };
+const OPS: usize = 512;
+const READERS: usize = 3;
+
+// Every byte written is its position in the stream, truncated. Capacities stay below 256, so
+// after skipping ahead a consumer's position is still unambiguous.
+fn run<const N: usize>(rng: &mut Rng, slowest: Slowest) {
+    let buffer = Buffer::<N>::new();
+    let readers = Readers::<READERS>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumers) = ring.split_broadcast(&readers, slowest).unwrap();
+
+    let mut written = 0;
+    let mut positions = [0; READERS];
+
+    for _ in 0..OPS {
+        let op = rng.next() as usize % 4;
+        let index = rng.next() as usize % READERS;
+        let consumer = &consumers[index];
+
+        match op {
+            // write, sometimes while a consumer holds on to a read grant
+            0 | 1 => {
+                let held = match op {
+                    1 => match consumer.read() {
+                        Ok(grant) => Some(grant),
+                        Err(Error::Lagged) => {
+                            resync(consumer, &mut positions[index], written, N);
+                            None
+                        }
+                        Err(_) => None,
+                    },
+                    _ => None,
+                };
+                let size = 1 + rng.next() as usize % N;
+
+                if let Ok(mut grant) = producer.grant_exact(size) {
+                    let used = rng.next() as usize % (size + 1);
+
+                    for byte in &mut grant.buf_mut()[..used] {
+                        *byte = written as u8;
+                        written += 1;
+                    }
+
+                    grant.commit(used);
+                }
+
+                drop(held);
+            }
+            // read
+            _ => match consumer.read() {
+                Ok(grant) => {
+                    let position = &mut positions[index];
+
+                    for &byte in grant.buf() {
+                        assert_eq!(byte, *position as u8, "consumer {index} out of order");
+                        *position += 1;
+                    }
+
+                    assert!(*position <= written);
+                    let used = rng.next() as usize % (grant.buf().len() + 1);
+                    *position -= grant.buf().len() - used;
+                    grant.commit(used);
+                }
+                Err(Error::InsufficientSize) => assert_eq!(positions[index], written),
+                Err(Error::Lagged) => {
+                    assert_eq!(slowest, Slowest::Lags);
+                    resync(consumer, &mut positions[index], written, N);
+                }
+                Err(err) => panic!("{err:?}"),
+            },
+        }
+
+        // once every consumer has caught up, the whole ring is free again
+        if positions.iter().all(|&position| position == written) {
+            drop(producer.grant_exact(N).unwrap());
+        }
+    }
+}
+
+// after being left behind, a consumer continues somewhere within the last `capacity` bytes
+fn resync(
+    consumer: &rbq::BroadcastConsumer,
+    position: &mut usize,
+    written: usize,
+    capacity: usize,
+) {
+    let oldest = (*position).max(written.saturating_sub(capacity));
+
+    match consumer.read() {
+        Ok(grant) => {
+            let skip = grant.buf()[0].wrapping_sub(oldest as u8) as usize;
+            *position = oldest + skip;
+            assert!(*position < written);
+            drop(grant);
+        }
+        Err(Error::InsufficientSize) => *position = written,
+        Err(err) => panic!("{err:?}"),
+    }
+}
+
+fn run_all(slowest: Slowest) {
+    let mut rng = Rng::from_env(0xb0ad_ca57);
+
+    for _ in 0..RUNS {
+        run::<1>(&mut rng, slowest);
+        run::<7>(&mut rng, slowest);
+        run::<16>(&mut rng, slowest);
+        run::<61>(&mut rng, slowest);
+    }
+}
+
+#[test]
+fn slowest_consumer_blocks() {
+    run_all(Slowest::Blocks);
+}
+
+#[test]
+fn slowest_consumer_lags() {
+    run_all(Slowest::Lags);
+}
+
+#[test]
+fn consumers_read_independently() {
+    let buffer = Buffer::<8>::new();
+    let readers = Readers::<2>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, [fast, slow]) = ring.split_broadcast(&readers, Slowest::Blocks).unwrap();
+
+    let mut grant = producer.grant_exact(6).unwrap();
+    grant.buf_mut().copy_from_slice(b"abcdef");
+    grant.commit(6);
+
+    let grant = fast.read().unwrap();
+    assert_eq!(grant.buf(), b"abcdef");
+    grant.commit(6);
+
+    // the slow consumer still holds the space
+    assert!(matches!(
+        producer.grant_exact(4),
+        Err(Error::InsufficientSize)
+    ));
+
+    let grant = slow.read().unwrap();
+    assert_eq!(grant.buf(), b"abcdef");
+    grant.commit(6);
+
+    assert!(producer.grant_exact(8).is_ok());
+    assert!(matches!(ring.split(), Err(Error::AlreadySplit)));
+}
+
+#[test]
+fn lagged_consumer_catches_up() {
+    let buffer = Buffer::<8>::new();
+    let readers = Readers::<2>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, [fast, slow]) = ring.split_broadcast(&readers, Slowest::Lags).unwrap();
+
+    for chunk in [b"abcd", b"efgh", b"ijkl"] {
+        let mut grant = producer.grant_exact(4).unwrap();
+        grant.buf_mut().copy_from_slice(chunk);
+        grant.commit(4);
+
+        let grant = fast.read().unwrap();
+        assert_eq!(grant.buf(), chunk);
+        grant.commit(4);
+    }
+
+    // left behind while the fast consumer kept up, so it continues where that one is
+    assert!(matches!(slow.read(), Err(Error::Lagged)));
+    assert!(matches!(slow.read(), Err(Error::InsufficientSize)));
+
+    let mut grant = producer.grant_exact(2).unwrap();
+    grant.buf_mut().copy_from_slice(b"mn");
+    grant.commit(2);
+
+    assert_eq!(slow.read().unwrap().buf(), b"mn");
+    assert_eq!(fast.read().unwrap().buf(), b"mn");
+}
+
+#[test]
+fn failed_splits_change_nothing() {
+    let (first, second) = (Buffer::<8>::new(), Buffer::<8>::new());
+    let readers = Readers::<1>::new();
+    let ring = Ring::new(&first);
+    let (producer, [consumer]) = ring.split_broadcast(&readers, Slowest::Blocks).unwrap();
+
+    // the readers are taken, which leaves the other ring free and the first one blocking
+    let other = Ring::new(&second);
+    assert!(matches!(
+        other.split_broadcast(&readers, Slowest::Lags),
+        Err(Error::AlreadySplit)
+    ));
+    assert!(other.split().is_ok());
+
+    producer.grant_exact(8).unwrap().commit(8);
+    assert!(matches!(
+        producer.grant_exact(1),
+        Err(Error::InsufficientSize)
+    ));
+    assert_eq!(consumer.read().unwrap().buf().len(), 8);
+
+    // and a ring that's taken leaves fresh readers free
+    let fresh = Readers::<1>::new();
+    assert!(matches!(
+        ring.split_broadcast(&fresh, Slowest::Lags),
+        Err(Error::AlreadySplit)
+    ));
+    assert!(other.split_broadcast(&fresh, Slowest::Lags).is_err());
+    let third = Buffer::<8>::new();
+    assert!(
+        Ring::new(&third)
+            .split_broadcast(&fresh, Slowest::Lags)
+            .is_ok()
+    );
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn stats_follow_the_slowest_consumer() {
+    let buffer = Buffer::<16>::new();
+    let readers = Readers::<2>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, [fast, slow]) = ring.split_broadcast(&readers, Slowest::Blocks).unwrap();
+
+    producer.grant_exact(10).unwrap().commit(10);
+    fast.read().unwrap().commit(10);
+    slow.read().unwrap().commit(4);
+
+    let stats = ring.stats();
+    assert_eq!(
+        (stats.used, stats.high_water, stats.written, stats.read),
+        (6, 10, 10, 14)
+    );
+
+    // the slow one is in the way
+    assert!(matches!(
+        producer.grant_exact(12),
+        Err(Error::InsufficientSize)
+    ));
+    assert!(matches!(fast.read(), Err(Error::InsufficientSize)));
+
+    slow.read().unwrap().commit(6);
+    let stats = ring.stats();
+    assert_eq!((stats.used, stats.full, stats.empty), (0, 1, 1));
+}
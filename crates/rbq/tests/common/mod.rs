@@ -0,0 +1,45 @@
+// Helpers shared by the integration tests: a waker for polling futures by hand and a seeded rng for
+// the randomized ones. Every test crate only uses some of them.
+
+#![allow(dead_code)]
+
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+struct Noop;
+
+impl Wake for Noop {
+    fn wake(self: Arc<Self>) {}
+}
+
+// a waker that does nothing, for futures that are polled again by hand
+pub fn noop_waker() -> Waker {
+    Waker::from(Arc::new(Noop))
+}
+
+pub fn poll_once<F: Future>(future: F) -> Poll<F::Output> {
+    let waker = noop_waker();
+    pin!(future).poll(&mut Context::from_waker(&waker))
+}
+
+// xorshift64*, plenty for picking operations
+pub struct Rng(u64);
+
+impl Rng {
+    // seeded from `RBQ_SEED` if it's set, so a failing run can be replayed
+    pub fn from_env(default: u64) -> Self {
+        match std::env::var("RBQ_SEED") {
+            Ok(seed) => Rng(seed.parse().expect("RBQ_SEED must be an integer")),
+            Err(_) => Rng(default),
+        }
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
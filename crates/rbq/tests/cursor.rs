@@ -0,0 +1,46 @@
+// Formatting straight into write grants through `WriteCursor`.
+
+use core::fmt::Write;
+
+use rbq::{Buffer, Error, Ring};
+
+#[test]
+fn commits_what_was_written() {
+    let buffer = Buffer::<32>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split().unwrap();
+
+    let mut cursor = producer.grant_max_remaining().unwrap().cursor();
+    write!(cursor, "adc{}: {:>4} mV", 3, 1250).unwrap();
+    cursor.append(b"\n").unwrap();
+    assert_eq!(cursor.written(), b"adc3: 1250 mV\n");
+    assert_eq!(cursor.remaining(), 32 - 14);
+    assert_eq!(cursor.finish(), Ok(14));
+
+    let grant = consumer.read().unwrap();
+    assert_eq!(grant.buf(), b"adc3: 1250 mV\n");
+    grant.commit(14);
+}
+
+#[test]
+fn rolls_back_on_overflow() {
+    let buffer = Buffer::<16>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split().unwrap();
+
+    let mut cursor = producer.grant_exact(8).unwrap().cursor();
+    cursor.append(b"abcd").unwrap();
+    assert!(write!(cursor, "too long").is_err());
+    assert!(cursor.overflowed());
+
+    // whatever comes after the overflow fails too, even if it would fit
+    assert_eq!(cursor.append(b"e"), Err(Error::InsufficientSize));
+    assert_eq!(cursor.finish(), Err(Error::InsufficientSize));
+    assert!(consumer.read().is_err());
+
+    // the grant was handed back, so the whole ring is free
+    let mut cursor = producer.grant_exact(16).unwrap().cursor();
+    cursor.append(b"fits").unwrap();
+    drop(cursor);
+    assert!(consumer.read().is_err());
+}
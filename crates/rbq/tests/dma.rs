@@ -0,0 +1,137 @@
+// Aligned grants and transfers, with plain slices and futures standing in for the dma.
+
+mod common;
+
+use std::task::Poll;
+
+use common::poll_once;
+use rbq::{Buffer, Error, Ring};
+
+#[test]
+fn aligned_grants_stay_aligned() {
+    let mut memory = [0u8; 256];
+    // a ring that starts out misaligned
+    let ring = Ring::from_slice(&mut memory[3..]).unwrap();
+    let (producer, consumer) = ring.split().unwrap();
+    let mut seq = 0u8;
+
+    for i in 0..1000 {
+        let size = 1 + i % 29;
+
+        if let Ok(mut grant) = producer.grant_exact_aligned(size, 8) {
+            assert_eq!(grant.buf().as_ptr().addr() % 8, 0);
+            assert_eq!(grant.buf().len(), size.next_multiple_of(8));
+
+            for byte in grant.buf_mut() {
+                seq = seq.wrapping_add(1).max(1);
+                *byte = seq;
+            }
+
+            let len = grant.buf().len();
+            grant.commit(len);
+        }
+
+        if i % 3 == 0 {
+            let grant = consumer.read_aligned(8).unwrap();
+            assert_eq!(grant.buf().len() % 8, 0);
+
+            // the padding in front of a grant never makes it into a read
+            assert_eq!(grant.buf().as_ptr().addr() % 8, 0);
+            assert!(!grant.buf().contains(&0));
+
+            let len = grant.buf().len();
+            grant.commit(len);
+        }
+    }
+
+    assert!(matches!(
+        producer.grant_exact_aligned(256, 8),
+        Err(Error::TooLarge)
+    ));
+}
+
+#[test]
+fn aligned_reads_wait_for_a_whole_transfer() {
+    let buffer = Buffer::<32>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split().unwrap();
+
+    producer.grant_exact(6).unwrap().commit(6);
+    assert!(matches!(
+        consumer.read_aligned(8),
+        Err(Error::InsufficientSize)
+    ));
+
+    producer.grant_exact(5).unwrap().commit(5);
+    let grant = consumer.read_aligned(8).unwrap();
+    assert_eq!(grant.buf().len(), 8);
+    grant.commit(8);
+    assert_eq!(consumer.read().unwrap().buf().len(), 3);
+}
+
+#[test]
+fn transfers_commit_what_was_moved() {
+    let buffer = Buffer::<16>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split().unwrap();
+
+    let grant = producer.grant_exact(8).unwrap();
+    let filled = poll_once(grant.transfer(async |buf: &mut [u8]| {
+        buf[..5].copy_from_slice(b"hello");
+        Ok::<_, ()>(5)
+    }));
+    assert_eq!(filled, Poll::Ready(Ok(5)));
+
+    // a failed transfer releases nothing
+    let grant = consumer.read().unwrap();
+    let sent = poll_once(grant.transfer(async |_: &[u8]| Err::<usize, _>("nak")));
+    assert_eq!(sent, Poll::Ready(Err("nak")));
+
+    // and neither does one that is dropped while in flight
+    let grant = consumer.read().unwrap();
+    let pending = poll_once(grant.transfer(async |buf: &[u8]| {
+        std::future::pending::<()>().await;
+        Ok::<_, ()>(buf.len())
+    }));
+    assert!(pending.is_pending());
+
+    let grant = consumer.read().unwrap();
+    let sent = poll_once(grant.transfer(async |buf: &[u8]| {
+        assert_eq!(buf, b"hello");
+        Ok::<_, ()>(3)
+    }));
+    assert_eq!(sent, Poll::Ready(Ok(3)));
+    assert_eq!(consumer.read().unwrap().buf(), b"lo");
+}
+
+#[test]
+fn aligned_grants_need_only_the_padding_they_skip() {
+    let buffer = Buffer::<32>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split().unwrap();
+
+    // as much as fits behind the padding in front of the start, all of it if the ring is aligned
+    let base = producer.grant_exact(1).unwrap().buf().as_ptr().addr();
+    let room = (32 - (base.next_multiple_of(8) - base)) & !7;
+    assert_eq!(
+        producer.grant_exact_aligned(room, 8).unwrap().buf().len(),
+        room
+    );
+    assert!(matches!(
+        producer.grant_exact_aligned(room + 1, 8),
+        Err(Error::TooLarge)
+    ));
+
+    producer.grant_exact(3).unwrap().commit(3);
+    consumer.read().unwrap().commit(3);
+
+    // neither a drop nor an empty commit publishes the padding
+    drop(producer.grant_exact_aligned(8, 8).unwrap());
+    producer.grant_exact_aligned(8, 8).unwrap().commit(0);
+    assert!(matches!(consumer.read(), Err(Error::InsufficientSize)));
+
+    let mut grant = producer.grant_exact_aligned(8, 8).unwrap();
+    grant.buf_mut().copy_from_slice(b"aligned!");
+    grant.commit(8);
+    assert_eq!(consumer.read_aligned(8).unwrap().buf(), b"aligned!");
+}
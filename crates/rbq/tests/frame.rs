@@ -0,0 +1,57 @@
+// Framed rings: what overwriting grants keep, and what they count as dropped.
+
+use rbq::{Buffer, Dropped, Error, FrameProducer, Ring};
+
+fn push_overwrite(producer: &FrameProducer, record: &[u8]) -> Result<(), Error> {
+    let mut grant = producer.grant_overwrite(record.len())?;
+    grant.buf_mut()[..record.len()].copy_from_slice(record);
+    grant.commit(record.len());
+    Ok(())
+}
+
+#[test]
+fn counts_every_record_that_is_lost() {
+    let buffer = Buffer::<16>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split_framed().unwrap();
+
+    // records of 1 + 4 bytes, the fourth wraps and has to push out the first two
+    for record in [b"aaaa", b"bbbb", b"cccc", b"dddd"] {
+        push_overwrite(&producer, record).unwrap();
+    }
+    assert_eq!(
+        consumer.dropped(),
+        Dropped {
+            records: 2,
+            bytes: 8
+        }
+    );
+
+    // the record the consumer holds can't be discarded, so the new one is lost instead
+    let grant = consumer.read().unwrap();
+    assert_eq!(grant.buf(), b"cccc");
+    assert_eq!(
+        push_overwrite(&producer, b"eeeeeeee"),
+        Err(Error::InsufficientSize)
+    );
+    assert_eq!(consumer.dropped().records, 3);
+    grant.commit();
+
+    // and so is one that would never fit
+    assert_eq!(push_overwrite(&producer, &[0; 16]), Err(Error::TooLarge));
+    // and whatever the caller gives up on before asking
+    producer.discard(300);
+    assert_eq!(
+        consumer.dropped(),
+        Dropped {
+            records: 5,
+            bytes: 8 + 8 + 16 + 300
+        }
+    );
+
+    let mut records = Vec::new();
+    consumer
+        .drain(|record| records.push(record.to_vec()))
+        .unwrap();
+    assert_eq!(records, [b"dddd"]);
+}
@@ -0,0 +1,107 @@
+// The `embedded-io` and `embedded-io-async` adapters, with both ends driven from one thread.
+
+#![cfg(feature = "embedded-io")]
+
+use embedded_io::{BufRead, Read, ReadReady, Write, WriteReady};
+use rbq::{Buffer, Ring};
+
+#[test]
+fn round_trips_through_every_read_flavour() {
+    let buffer = Buffer::<16>::new();
+    let ring = Ring::new(&buffer);
+    let (mut producer, mut consumer) = ring.split().unwrap();
+
+    let data = (0..200u8).collect::<Vec<_>>();
+    let (mut sent, mut received) = (0, Vec::new());
+
+    while received.len() < data.len() {
+        if sent < data.len() && producer.write_ready().unwrap() {
+            let chunk = &data[sent..(sent + 7).min(data.len())];
+            sent += producer.write(chunk).unwrap();
+        }
+
+        if !consumer.read_ready().unwrap() {
+            continue;
+        }
+
+        if received.len() % 2 == 0 {
+            let mut buf = [0; 5];
+            let n = Read::read(&mut consumer, &mut buf).unwrap();
+            received.extend_from_slice(&buf[..n]);
+        } else {
+            let buf = consumer.fill_buf().unwrap();
+            let n = buf.len().min(3);
+            received.extend_from_slice(&buf[..n]);
+            consumer.consume(n);
+        }
+    }
+
+    assert_eq!(received, data);
+    assert!(!consumer.read_ready().unwrap());
+}
+
+#[test]
+fn consume_stays_within_the_data() {
+    let buffer = Buffer::<16>::new();
+    let ring = Ring::new(&buffer);
+    let (mut producer, mut consumer) = ring.split().unwrap();
+
+    // nothing to consume is fine
+    consumer.consume(4);
+
+    producer.write_all(b"abc").unwrap();
+    assert_eq!(consumer.fill_buf().unwrap(), b"abc");
+
+    // and consuming more than there is only takes what's there
+    consumer.consume(10);
+    assert!(!consumer.read_ready().unwrap());
+
+    producer.write_all(b"de").unwrap();
+    assert_eq!(consumer.fill_buf().unwrap(), b"de");
+}
+
+#[cfg(feature = "embedded-io-async")]
+mod common;
+
+#[cfg(feature = "embedded-io-async")]
+mod asynchronous {
+    use std::task::Poll;
+
+    use embedded_io_async::{BufRead, Read, Write};
+    use rbq::{Buffer, Ring};
+
+    use super::common::poll_once;
+
+    #[test]
+    fn waits_for_room_and_for_data() {
+        let buffer = Buffer::<8>::new();
+        let ring = Ring::new(&buffer);
+        let (mut producer, mut consumer) = ring.split().unwrap();
+        let mut buf = [0; 8];
+
+        assert!(poll_once(Read::read(&mut consumer, &mut buf)).is_pending());
+        assert!(poll_once(consumer.fill_buf()).is_pending());
+
+        assert_eq!(poll_once(producer.write(b"0123456789")), Poll::Ready(Ok(8)));
+        assert!(poll_once(producer.write(b"89")).is_pending());
+
+        assert_eq!(
+            poll_once(Read::read(&mut consumer, &mut buf[..5])),
+            Poll::Ready(Ok(5))
+        );
+        assert_eq!(&buf[..5], b"01234");
+
+        let Poll::Ready(Ok(rest)) = poll_once(consumer.fill_buf()) else {
+            panic!("the rest of the data is gone");
+        };
+        assert_eq!(rest, b"567");
+        consumer.consume(3);
+
+        assert_eq!(poll_once(producer.write(b"89")), Poll::Ready(Ok(2)));
+        assert_eq!(
+            poll_once(Read::read(&mut consumer, &mut buf)),
+            Poll::Ready(Ok(2))
+        );
+        assert_eq!(&buf[..2], b"89");
+    }
+}
@@ -0,0 +1,188 @@
+// Producer and consumer interleavings on the lock-free book, explored exhaustively by loom. Run
+// with `RUSTFLAGS="--cfg loom" cargo test -p rbq --test loom --release`. Every access to a grant's
+// buffer is checked against the other side's, so an ordering too weak to publish the data fails
+// here as a causality violation.
+
+#![cfg(loom)]
+
+use loom::future::block_on;
+use loom::model::Builder;
+use loom::thread;
+use rbq::{Buffer, Consumer, Error, FrameConsumer, FrameProducer, Producer, Ring};
+
+fn model(f: impl Fn() + Sync + Send + 'static) {
+    let mut builder = Builder::new();
+    builder.preemption_bound.get_or_insert(3);
+    builder.check(f);
+}
+
+// Loom threads need `'static` handles, so the buffer is allocated per execution and freed once
+// `f` is done with it. Leaking it instead would leak the wakers, which loom reports as an error.
+fn with_split<const N: usize>(f: impl FnOnce(Producer<'static>, Consumer<'static>)) {
+    let buffer = Box::into_raw(Box::new(Buffer::<N>::new()));
+    let (producer, consumer) = Ring::new(unsafe { &*buffer }).split().unwrap();
+    f(producer, consumer);
+    // every handle has been joined or dropped by now
+    drop(unsafe { Box::from_raw(buffer) });
+}
+
+// `with_split` for framed rings
+fn with_split_framed<const N: usize>(
+    f: impl FnOnce(FrameProducer<'static>, FrameConsumer<'static>),
+) {
+    let buffer = Box::into_raw(Box::new(Buffer::<N>::new()));
+    let (producer, consumer) = Ring::new(unsafe { &*buffer }).split_framed().unwrap();
+    f(producer, consumer);
+    drop(unsafe { Box::from_raw(buffer) });
+}
+
+#[test]
+fn reads_only_committed_bytes() {
+    model(|| {
+        with_split::<4>(|producer, consumer| {
+            // three records of two bytes each, so the last one wraps around
+            let writer = thread::spawn(move || {
+                for value in 1..=3 {
+                    loop {
+                        if let Ok(mut grant) = producer.grant_exact(2) {
+                            grant.buf_mut().fill(value);
+                            grant.commit(2);
+                            break;
+                        }
+
+                        thread::yield_now();
+                    }
+                }
+            });
+
+            let mut read = 0;
+
+            while read < 6 {
+                let Ok(grant) = consumer.read() else {
+                    thread::yield_now();
+                    continue;
+                };
+
+                for &byte in grant.buf() {
+                    assert_eq!(byte, 1 + read / 2);
+                    read += 1;
+                }
+
+                let len = grant.buf().len();
+                grant.commit(len);
+            }
+
+            writer.join().unwrap();
+        });
+    });
+}
+
+#[test]
+fn partial_commits_only_publish_what_was_used() {
+    model(|| {
+        with_split::<4>(|producer, consumer| {
+            let writer = thread::spawn(move || {
+                let mut grant = producer.grant_exact(3).unwrap();
+                grant.buf_mut().copy_from_slice(&[1, 2, 0xee]);
+                grant.commit(2);
+            });
+
+            let mut seen = Vec::new();
+
+            while seen.len() < 2 {
+                match consumer.read() {
+                    Ok(grant) => {
+                        seen.extend_from_slice(grant.buf());
+                        let len = grant.buf().len();
+                        grant.commit(len);
+                    }
+                    Err(_) => thread::yield_now(),
+                }
+            }
+
+            writer.join().unwrap();
+            assert_eq!(seen, [1, 2]);
+            assert!(consumer.read().is_err());
+        });
+    });
+}
+
+#[test]
+fn reader_wakeup_is_not_lost() {
+    model(|| {
+        with_split::<4>(|producer, consumer| {
+            let writer = thread::spawn(move || {
+                producer.grant_exact(3).unwrap().commit(3);
+            });
+
+            // a lost wakeup leaves this parked forever, which loom reports as a deadlock
+            let grant = block_on(consumer.read_async()).unwrap();
+            assert_eq!(grant.buf().len(), 3);
+            grant.commit(3);
+
+            writer.join().unwrap();
+        });
+    });
+}
+
+#[test]
+fn writer_wakeup_is_not_lost() {
+    model(|| {
+        with_split::<4>(|producer, consumer| {
+            producer.grant_exact(4).unwrap().commit(4);
+
+            let reader = thread::spawn(move || {
+                let grant = consumer.read().unwrap();
+                grant.commit(4);
+            });
+
+            let grant = block_on(producer.grant_exact_async(2)).unwrap();
+            assert_eq!(grant.buf().len(), 2);
+            drop(grant);
+
+            reader.join().unwrap();
+        });
+    });
+}
+
+#[test]
+fn overwriting_never_fails_a_read() {
+    model(|| {
+        with_split_framed::<4>(|producer, consumer| {
+            for value in 1..=2 {
+                let mut grant = producer.grant(1).unwrap();
+                grant.buf_mut()[0] = value;
+                grant.commit(1);
+            }
+
+            // the ring is full, so this one has to discard the oldest record first
+            let writer = thread::spawn(move || {
+                loop {
+                    if let Ok(mut grant) = producer.grant_overwrite(1) {
+                        grant.buf_mut()[0] = 3;
+                        grant.commit(1);
+                        break;
+                    }
+
+                    thread::yield_now();
+                }
+            });
+
+            // a record being discarded is nothing to read yet, not a grant in progress
+            let mut last = 0;
+
+            while last < 3 {
+                let grant = block_on(consumer.poll(|c| match c.read() {
+                    Err(Error::InsufficientSize) => None,
+                    result => Some(result),
+                }))
+                .unwrap();
+                assert!(grant.buf()[0] > last);
+                last = grant.buf()[0];
+                grant.commit();
+            }
+
+            writer.join().unwrap();
+        });
+    });
+}
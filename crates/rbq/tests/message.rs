@@ -0,0 +1,85 @@
+// Postcard messages through a ring, borrowed and owned.
+
+#![cfg(feature = "postcard")]
+
+use rbq::{Buffer, Error, Ring};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Command<'a> {
+    Reset,
+    Echo(&'a str),
+    Blink { led: u8, period_ms: u32 },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Telemetry {
+    uptime_ms: u64,
+    temps: [i16; 4],
+}
+
+#[test]
+fn borrows_straight_from_the_ring() {
+    let buffer = Buffer::<64>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split_message().unwrap();
+
+    let commands = [
+        Command::Echo("hello"),
+        Command::Reset,
+        Command::Blink {
+            led: 2,
+            period_ms: 500,
+        },
+    ];
+
+    for command in &commands {
+        producer.send(command).unwrap();
+    }
+
+    for command in &commands {
+        let grant = consumer.read().unwrap();
+        let decoded: Command = grant.decode().unwrap();
+        assert_eq!(&decoded, command);
+
+        if let Command::Echo(text) = decoded {
+            let range = grant.buf().as_ptr_range();
+            assert!(range.contains(&text.as_ptr()));
+        }
+
+        grant.commit();
+    }
+
+    assert!(matches!(consumer.read(), Err(Error::InsufficientSize)));
+}
+
+#[test]
+fn owned_messages_and_malformed_records() {
+    let buffer = Buffer::<64>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split_message().unwrap();
+
+    let telemetry = Telemetry {
+        uptime_ms: 1 << 40,
+        temps: [215, -40, 0, 1000],
+    };
+    producer.send(&telemetry).unwrap();
+    assert_eq!(consumer.recv::<Telemetry>(), Ok(telemetry));
+
+    // the wrong type, with bytes left over, is skipped rather than stuck
+    producer.send(&(7u8, 8u8)).unwrap();
+    producer.send(&9u16).unwrap();
+    assert_eq!(consumer.recv::<u8>(), Err(Error::Codec));
+    assert_eq!(consumer.recv::<u16>(), Ok(9));
+
+    // and a record that ends early doesn't decode either
+    producer.send(&0x80u8).unwrap();
+    let grant = consumer.read().unwrap();
+    assert_eq!(grant.decode::<u32>(), Err(Error::Codec));
+    grant.commit();
+
+    assert!(matches!(
+        producer.send(&[0u8; 64][..]),
+        Err(Error::TooLarge)
+    ));
+}
@@ -0,0 +1,298 @@
+// Exercises every kind of grant with the storage being read and written through them, so the
+// aliasing rules can be checked with `cargo miri test -p rbq --test miri`. Also runs as a plain
+// host test.
+
+// `Buffer::new` is only a `const fn` outside of loom, and the static below needs it
+#![cfg(not(loom))]
+
+mod common;
+
+use std::mem::MaybeUninit;
+use std::pin::pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::thread;
+
+use common::noop_waker;
+use rbq::{Buffer, Error, Ring, TypedBuffer, TypedRing};
+
+const ROUNDS: usize = if cfg!(miri) { 64 } else { 4096 };
+
+fn fill(buf: &mut [u8], seq: &mut u8) {
+    for byte in buf {
+        *byte = *seq;
+        *seq = seq.wrapping_add(1);
+    }
+}
+
+fn check(buf: &[u8], seq: &mut u8) {
+    for byte in buf {
+        assert_eq!(*byte, *seq);
+        *seq = seq.wrapping_add(1);
+    }
+}
+
+#[test]
+fn grants_overlap_with_each_other() {
+    let buffer = Buffer::<32>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split().unwrap();
+    let (mut wseq, mut rseq) = (0, 0);
+
+    for i in 0..ROUNDS {
+        let size = 1 + i % 13;
+
+        if let Ok(mut grant) = producer.grant_exact(size) {
+            // a read grant is held while the write grant is filled
+            let read = consumer.read();
+            fill(grant.buf_mut(), &mut wseq);
+
+            if let Ok(read) = read {
+                let len = read.buf().len();
+                check(read.buf(), &mut rseq);
+                read.commit(len);
+            }
+
+            grant.commit(size);
+        }
+    }
+}
+
+#[test]
+fn split_reads_across_the_wrap() {
+    let buffer = Buffer::<24>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split().unwrap();
+    let (mut wseq, mut rseq) = (0, 0);
+
+    for i in 0..ROUNDS {
+        while let Ok(mut grant) = producer.grant_max_remaining() {
+            let len = grant.buf().len().min(1 + i % 7);
+            fill(&mut grant.buf_mut()[..len], &mut wseq);
+            grant.commit(len);
+        }
+
+        let grant = consumer.split_read().unwrap();
+        let (tail, head) = grant.bufs();
+        let used = (tail.len() + head.len()).min(1 + i % 11);
+        check(&[tail, head].concat()[..used], &mut rseq);
+        grant.commit(used);
+    }
+}
+
+#[test]
+fn framed_and_overwriting() {
+    let buffer = Buffer::<40>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split_framed().unwrap();
+
+    for i in 0..ROUNDS {
+        let len = i % 9;
+        let mut grant = producer.grant_overwrite(len).unwrap();
+        grant.buf_mut()[..len].fill(len as u8);
+        grant.commit(len);
+
+        if i % 3 == 0 {
+            let frame = consumer.read().unwrap();
+            assert!(frame.buf().iter().all(|&b| b as usize == frame.buf().len()));
+            frame.commit();
+        }
+    }
+
+    assert!(consumer.dropped().records > 0);
+}
+
+#[test]
+fn typed_elements_are_dropped() {
+    let marker = Rc::new(());
+
+    {
+        let buffer = TypedBuffer::<Rc<()>, 8>::new();
+        let ring = TypedRing::new(&buffer);
+        let (producer, consumer) = ring.split().unwrap();
+
+        for i in 0..ROUNDS {
+            if let Ok(mut grant) = producer.grant_exact(1 + i % 3) {
+                grant.buf_uninit().iter_mut().for_each(|slot| {
+                    slot.write(marker.clone());
+                });
+                let len = grant.buf_uninit().len();
+                unsafe { grant.commit_unchecked(len) };
+            }
+
+            if i % 2 == 0 {
+                let grant = consumer.read().unwrap();
+                assert!(grant.buf().iter().all(|rc| Rc::ptr_eq(rc, &marker)));
+                grant.commit(1);
+            }
+        }
+
+        assert!(Rc::strong_count(&marker) > 1);
+    }
+
+    assert_eq!(Rc::strong_count(&marker), 1);
+}
+
+#[test]
+fn commits_stop_at_the_end_of_the_grant() {
+    let marker = Rc::new(());
+    let buffer = TypedBuffer::<Rc<()>, 8>::new();
+    let ring = TypedRing::new(&buffer);
+    let (producer, consumer) = ring.split().unwrap();
+
+    let mut grant = producer.grant_exact(2).unwrap();
+    for slot in grant.buf_uninit() {
+        slot.write(marker.clone());
+    }
+    unsafe { grant.commit_unchecked(usize::MAX) };
+
+    // only what was granted is dropped, whatever the commit asks for
+    consumer.read().unwrap().commit(usize::MAX);
+    assert_eq!(Rc::strong_count(&marker), 1);
+
+    let mut grant = producer.grant_exact(3).unwrap();
+    for slot in grant.buf_uninit() {
+        slot.write(marker.clone());
+    }
+    unsafe { grant.commit_unchecked(3) };
+
+    consumer.split_read().unwrap().commit(usize::MAX);
+    assert_eq!(Rc::strong_count(&marker), 1);
+    assert!(consumer.read().is_err());
+}
+
+#[test]
+fn typed_elements_are_aligned() {
+    #[derive(Clone, Copy)]
+    #[repr(align(32))]
+    struct Block(u8);
+
+    let buffer = TypedBuffer::<Block, 5>::new();
+    let ring = TypedRing::new(&buffer);
+    let (producer, consumer) = ring.split().unwrap();
+
+    for i in 0..ROUNDS {
+        let mut grant = producer.grant_exact(2).unwrap();
+        for slot in grant.buf_uninit() {
+            assert!((slot as *mut MaybeUninit<Block>).is_aligned());
+            slot.write(Block(i as u8));
+        }
+        unsafe { grant.commit_unchecked(2) };
+
+        let grant = consumer.read().unwrap();
+        assert!(grant.buf().iter().all(|block| block.0 == i as u8));
+        grant.commit(2);
+    }
+}
+
+#[test]
+fn multi_producer_reservations() {
+    let buffer = Buffer::<16>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split_multi().unwrap();
+    let other = producer.clone();
+
+    for i in 0..ROUNDS {
+        let mut first = producer.grant(3).unwrap();
+        let mut second = other.grant(2).unwrap();
+        second.buf_mut().copy_from_slice(&[i as u8; 2]);
+        second.commit(2);
+        first.buf_mut().copy_from_slice(&[!(i as u8); 3]);
+        first.commit(3);
+
+        let grant = consumer.read().unwrap();
+        assert_eq!(grant.buf(), [!(i as u8); 3]);
+        grant.commit();
+        let grant = consumer.read().unwrap();
+        assert_eq!(grant.buf(), [i as u8; 2]);
+        grant.commit();
+    }
+}
+
+#[test]
+fn threads_through_a_static() {
+    static BUFFER: Buffer<64> = Buffer::new();
+    static RING: Ring<'static> = Ring::new(&BUFFER);
+    let (producer, consumer) = RING.split().unwrap();
+    assert!(RING.split().is_err());
+
+    let writer = thread::spawn(move || {
+        let mut seq = 0;
+        let mut written = 0;
+
+        while written < ROUNDS {
+            if let Ok(mut grant) = producer.grant_max_remaining() {
+                let len = grant.buf().len().min(ROUNDS - written);
+                fill(&mut grant.buf_mut()[..len], &mut seq);
+                grant.commit(len);
+                written += len;
+            }
+            thread::yield_now();
+        }
+    });
+
+    let mut seq = 0;
+    let mut read = 0;
+
+    while read < ROUNDS {
+        if let Ok(grant) = consumer.read() {
+            let len = grant.buf().len();
+            check(grant.buf(), &mut seq);
+            grant.commit(len);
+            read += len;
+        }
+        thread::yield_now();
+    }
+
+    writer.join().unwrap();
+}
+
+#[test]
+fn async_grants_wait_for_each_other() {
+    let buffer = Buffer::<8>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split().unwrap();
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+
+    let mut read = pin!(consumer.read_async());
+    assert!(read.as_mut().poll(&mut cx).is_pending());
+    producer.grant_exact(6).unwrap().commit(6);
+    let Poll::Ready(Ok(grant)) = read.as_mut().poll(&mut cx) else {
+        panic!("no data after a commit");
+    };
+
+    let mut write = pin!(producer.grant_exact_async(4));
+    assert!(write.as_mut().poll(&mut cx).is_pending());
+    grant.commit(6);
+    assert!(matches!(write.as_mut().poll(&mut cx), Poll::Ready(Ok(_))));
+}
+
+#[test]
+fn rings_over_a_borrowed_slice() {
+    assert!(matches!(
+        Ring::from_slice(&mut [0; 8]),
+        Err(Error::TooSmall)
+    ));
+
+    // deliberately misaligned, the bookkeeping has to be placed past the start
+    let mut memory = vec![0xa5; 256];
+    let ring = Ring::from_slice(&mut memory[3..]).unwrap();
+    let (producer, consumer) = ring.split().unwrap();
+    let (mut wseq, mut rseq) = (0, 0);
+
+    for i in 0..ROUNDS {
+        if let Ok(mut grant) = producer.grant_exact(1 + i % 29) {
+            let len = grant.buf().len();
+            fill(grant.buf_mut(), &mut wseq);
+            grant.commit(len);
+        }
+
+        if i % 3 != 0 {
+            let grant = consumer.read().unwrap();
+            let len = grant.buf().len();
+            check(grant.buf(), &mut rseq);
+            grant.commit(len);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+// Reference model for the byte ring, shared by the property test and the fuzz target.
+//
+// The input is a plain byte string: the first byte picks the ring size and every following
+// three bytes decode to one operation. The ring is checked against a `VecDeque` after every
+// operation, both for its contents and for space that should be available but isn't.
+
+use std::collections::VecDeque;
+
+use rbq::{Buffer, Error, GrantRead, Ring};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    Write { size: usize, used: usize },
+    WriteRemaining { used: usize },
+    // fills a grant and drops it without committing
+    Abandon { size: usize },
+    Read { used: usize },
+    SplitRead { used: usize },
+    // takes a read grant and holds on to it across the following operations, or lets go of it
+    Hold,
+}
+
+macro_rules! sizes {
+    ($($n:literal)*) => {
+        pub const SIZES: &[usize] = &[$($n),*];
+
+        fn dispatch(capacity: usize, ops: &[Op]) {
+            match capacity {
+                $($n => run::<$n>(ops),)*
+                _ => unreachable!(),
+            }
+        }
+    };
+}
+
+sizes!(1 2 3 4 5 7 8 13 16 31 64 127);
+
+pub fn decode(data: &[u8]) -> (usize, Vec<Op>) {
+    let Some((&first, rest)) = data.split_first() else {
+        return (SIZES[0], Vec::new());
+    };
+
+    let capacity = SIZES[first as usize % SIZES.len()];
+    let ops = rest
+        .chunks_exact(3)
+        .map(|op| {
+            let (a, b) = (op[1] as usize, op[2] as usize);
+
+            match op[0] % 6 {
+                0 => Op::Write { size: a, used: b },
+                1 => Op::WriteRemaining { used: a },
+                2 => Op::Abandon { size: a },
+                3 => Op::Read { used: a },
+                4 => Op::SplitRead { used: a },
+                _ => Op::Hold,
+            }
+        })
+        .collect();
+
+    (capacity, ops)
+}
+
+pub fn check(data: &[u8]) {
+    let (capacity, ops) = decode(data);
+    dispatch(capacity, &ops);
+}
+
+fn run<const N: usize>(ops: &[Op]) {
+    let buffer = Buffer::<N>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split().unwrap();
+
+    let mut model = VecDeque::new();
+    let mut seq = 0u8;
+    let mut held: Option<GrantRead> = None;
+
+    let mut fill = |buf: &mut [u8], model: &mut VecDeque<u8>| {
+        for byte in buf {
+            *byte = seq;
+            model.push_back(seq);
+            seq = seq.wrapping_add(1);
+        }
+    };
+
+    for op in ops {
+        match *op {
+            Op::Write { size, used } => {
+                let size = size % (N + 2);
+
+                match producer.grant_exact(size) {
+                    Ok(mut grant) => {
+                        assert_eq!(grant.buf().len(), size);
+                        assert!(model.len() + size <= N);
+                        let used = used % (size + 1);
+                        fill(&mut grant.buf_mut()[..used], &mut model);
+                        grant.commit(used);
+                    }
+                    Err(Error::TooLarge) => assert!(size > N),
+                    // an empty ring has room for any grant up to its capacity
+                    Err(_) => assert!(size <= N && (size == 0 || !model.is_empty())),
+                }
+            }
+            Op::WriteRemaining { used } => match producer.grant_max_remaining() {
+                Ok(mut grant) => {
+                    let len = grant.buf().len();
+                    assert!(model.len() + len <= N);
+                    assert!(!model.is_empty() || len == N);
+                    let used = used % (len + 1);
+                    fill(&mut grant.buf_mut()[..used], &mut model);
+                    grant.commit(used);
+                }
+                Err(_) => assert!(!model.is_empty()),
+            },
+            Op::Abandon { size } => {
+                if let Ok(mut grant) = producer.grant_exact(size % (N + 1)) {
+                    grant.buf_mut().fill(0xee);
+                }
+            }
+            Op::Read { used } => match consumer.read() {
+                Ok(grant) => {
+                    let buf = grant.buf();
+                    assert!(!buf.is_empty());
+                    assert!(buf.iter().eq(model.iter().take(buf.len())));
+                    let used = used % (buf.len() + 1);
+                    grant.commit(used);
+                    model.drain(..used);
+                }
+                Err(Error::GrantInProgress) => assert!(held.is_some()),
+                Err(_) => assert!(model.is_empty()),
+            },
+            Op::SplitRead { used } => match consumer.split_read() {
+                Ok(grant) => {
+                    let (tail, head) = grant.bufs();
+                    assert!(!tail.is_empty());
+                    assert!(tail.iter().chain(head).eq(model.iter()));
+                    let used = used % (grant.combined_len() + 1);
+                    grant.commit(used);
+                    model.drain(..used);
+                }
+                Err(Error::GrantInProgress) => assert!(held.is_some()),
+                Err(_) => assert!(model.is_empty()),
+            },
+            Op::Hold => match held.take() {
+                Some(grant) => drop(grant),
+                None => {
+                    held = consumer.read().ok();
+                    assert_eq!(held.is_some(), !model.is_empty());
+                }
+            },
+        }
+    }
+
+    drop(held);
+
+    while let Ok(grant) = consumer.read() {
+        let len = grant.buf().len();
+        assert!(grant.buf().iter().eq(model.iter().take(len)));
+        grant.commit(len);
+        model.drain(..len);
+    }
+
+    assert!(model.is_empty());
+}
@@ -0,0 +1,131 @@
+// Multi-producer rings: records land in reservation order, and reservations that are given up on
+// never show up at all.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use rbq::{Buffer, FrameConsumer, MultiProducer, Ring};
+
+fn push(producer: &MultiProducer, record: &[u8]) {
+    let mut grant = producer.grant(record.len()).unwrap();
+    grant.buf_mut().copy_from_slice(record);
+    grant.commit(record.len());
+}
+
+fn drain(consumer: &FrameConsumer) -> Vec<Vec<u8>> {
+    let mut records = Vec::new();
+    consumer
+        .drain(|record| records.push(record.to_vec()))
+        .unwrap();
+    records
+}
+
+#[test]
+fn commits_land_in_reservation_order() {
+    let buffer = Buffer::<32>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split_multi().unwrap();
+    let other = producer.clone();
+
+    let mut first = producer.grant(4).unwrap();
+    push(&other, b"second");
+
+    // held back until the older reservation is done
+    assert!(consumer.read().is_err());
+
+    first.buf_mut().copy_from_slice(b"1st!");
+    first.commit(4);
+    assert_eq!(drain(&consumer), [&b"1st!"[..], b"second"]);
+}
+
+#[test]
+fn dropped_reservations_are_skipped() {
+    let buffer = Buffer::<512>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split_multi().unwrap();
+
+    let oldest = producer.grant(200).unwrap();
+    let mut middle = producer.grant(8).unwrap();
+    let newest = producer.grant(2).unwrap();
+
+    // the newest one just gives its space back
+    drop(newest);
+    // the oldest one is in the way of the middle one, so it's published as padding
+    drop(oldest);
+
+    // as is whatever a short record leaves over
+    middle.buf_mut()[..2].copy_from_slice(b"hi");
+    middle.commit(2);
+
+    // one read gets past the padding in front of the record
+    let grant = consumer.read().unwrap();
+    assert_eq!(grant.buf(), b"hi");
+    grant.commit();
+    assert!(consumer.read().is_err());
+
+    #[cfg(feature = "stats")]
+    assert_eq!(ring.stats().used, 0);
+}
+
+#[test]
+fn writers_on_several_threads() {
+    const WRITERS: u8 = 3;
+    const RECORDS: u32 = if cfg!(miri) { 32 } else { 2_000 };
+
+    // stops the writers even if the reader fails, rather than leaving them waiting for room
+    struct Done<'a>(&'a AtomicBool);
+
+    impl Drop for Done<'_> {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+
+    let buffer = Buffer::<97>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split_multi().unwrap();
+    let done = AtomicBool::new(false);
+
+    thread::scope(|s| {
+        for writer in 0..WRITERS {
+            let producer = producer.clone();
+            let done = &done;
+
+            s.spawn(move || {
+                let mut seq = 0;
+
+                while seq < RECORDS && !done.load(Ordering::Relaxed) {
+                    let Ok(mut grant) = producer.grant(1 + 4 + writer as usize) else {
+                        thread::yield_now();
+                        continue;
+                    };
+
+                    let record = grant.buf_mut();
+                    record[0] = writer;
+                    record[1..5].copy_from_slice(&seq.to_le_bytes());
+                    grant.commit(5);
+                    seq += 1;
+                }
+            });
+        }
+
+        let _done = Done(&done);
+        let mut next = [0; WRITERS as usize];
+
+        while next.iter().any(|&seq| seq < RECORDS) {
+            let Ok(grant) = consumer.read() else {
+                thread::yield_now();
+                continue;
+            };
+
+            if let [writer, seq @ ..] = grant.buf() {
+                let writer = *writer as usize;
+                let seq = u32::from_le_bytes(seq.try_into().unwrap());
+                assert_eq!(seq, next[writer], "writer {writer}");
+                next[writer] += 1;
+            }
+
+            grant.commit();
+        }
+    });
+}
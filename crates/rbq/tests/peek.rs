@@ -0,0 +1,104 @@
+// Peeking, skipping and waiting for a minimum amount of data on the consumer side.
+
+mod common;
+
+use std::future::Future;
+use std::pin::pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use common::noop_waker;
+use rbq::{Buffer, Consumer, Error, Producer, Ring, TypedBuffer, TypedRing};
+
+fn push(producer: &Producer, bytes: &[u8]) {
+    let mut grant = producer.grant_exact(bytes.len()).unwrap();
+    grant.buf_mut().copy_from_slice(bytes);
+    grant.commit(bytes.len());
+}
+
+fn peeked(consumer: &Consumer, n: usize) -> Vec<u8> {
+    let grant = consumer.peek(n).unwrap();
+    let (tail, head) = grant.bufs();
+    [tail, head].concat()
+}
+
+#[test]
+fn peeks_across_the_wrap() {
+    let buffer = Buffer::<8>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split().unwrap();
+
+    push(&producer, b"xxxxxx");
+    consumer.skip(6).unwrap();
+    push(&producer, b"ab");
+    push(&producer, b"cdef");
+
+    // looking doesn't use anything up
+    assert_eq!(peeked(&consumer, 1), b"a");
+    assert_eq!(peeked(&consumer, 3), b"abc");
+    assert_eq!(peeked(&consumer, 6), b"abcdef");
+    assert!(matches!(consumer.peek(7), Err(Error::InsufficientSize)));
+    assert!(matches!(consumer.peek(9), Err(Error::TooLarge)));
+
+    // committing a peek moves on like any other read grant
+    consumer.peek(3).unwrap().commit(3);
+    assert_eq!(peeked(&consumer, 3), b"def");
+}
+
+#[test]
+fn skips_all_or_nothing() {
+    let buffer = Buffer::<8>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split().unwrap();
+
+    push(&producer, b"abcde");
+    assert!(matches!(consumer.skip(6), Err(Error::InsufficientSize)));
+    assert_eq!(consumer.skip(0), Ok(()));
+    assert_eq!(consumer.skip(2), Ok(()));
+    assert_eq!(consumer.read().unwrap().buf(), b"cde");
+
+    // skipped elements are dropped like read ones
+    let buffer = TypedBuffer::<Rc<()>, 4>::new();
+    let ring = TypedRing::new(&buffer);
+    let (producer, consumer) = ring.split().unwrap();
+    let rc = Rc::new(());
+
+    let mut grant = producer.grant_exact(3).unwrap();
+    for slot in grant.buf_uninit() {
+        slot.write(rc.clone());
+    }
+    unsafe { grant.commit_unchecked(3) };
+
+    consumer.skip(2).unwrap();
+    assert_eq!(Rc::strong_count(&rc), 2);
+}
+
+#[test]
+fn waits_for_enough_data() {
+    let buffer = Buffer::<8>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split().unwrap();
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+
+    {
+        let mut read = pin!(consumer.read_at_least(4));
+        assert!(read.as_mut().poll(&mut cx).is_pending());
+        push(&producer, b"ab");
+        assert!(read.as_mut().poll(&mut cx).is_pending());
+        push(&producer, b"cdef");
+
+        let Poll::Ready(Ok(grant)) = read.as_mut().poll(&mut cx) else {
+            panic!("not enough data after the second commit");
+        };
+        assert_eq!(grant.combined_len(), 6);
+        grant.commit(6);
+    }
+
+    // never waits for what can't ever be there
+    let mut read = pin!(consumer.read_at_least(9));
+    assert!(matches!(
+        read.as_mut().poll(&mut cx),
+        Poll::Ready(Err(Error::TooLarge))
+    ));
+}
@@ -0,0 +1,186 @@
+// Recovery of persistent rings, with a byte array standing in for the no-init region and dropping
+// every handle standing in for the reset.
+
+// persistent rings aren't built under loom
+#![cfg(not(loom))]
+
+use std::collections::VecDeque;
+use std::mem;
+
+use rbq::{Boot, Dropped, Error, FrameConsumer, FrameProducer, Ring};
+
+// aligned, so the seal is known to sit at the very start
+#[repr(align(8))]
+struct Memory([u8; 512]);
+
+fn push(producer: &FrameProducer, record: &[u8]) {
+    let mut grant = producer.grant(record.len()).unwrap();
+    grant.buf_mut()[..record.len()].copy_from_slice(record);
+    grant.commit(record.len());
+}
+
+fn drain(consumer: &FrameConsumer) -> Vec<Vec<u8>> {
+    let mut records = Vec::new();
+
+    while let Ok(grant) = consumer.read() {
+        records.push(grant.buf().to_vec());
+        grant.commit();
+    }
+
+    records
+}
+
+fn recover(memory: &mut [u8]) -> (Boot, Vec<Vec<u8>>) {
+    let (ring, boot) = Ring::from_persistent(memory).unwrap();
+    let (_, consumer) = ring.split_framed().unwrap();
+    (boot, drain(&consumer))
+}
+
+#[test]
+fn starts_cold_on_fresh_memory() {
+    for fill in [0x00, 0xff, 0xa5] {
+        let mut memory = Memory([fill; 512]);
+        assert_eq!(recover(&mut memory.0), (Boot::Cold, vec![]));
+    }
+
+    assert!(matches!(
+        Ring::from_persistent(&mut [0; 16]),
+        Err(Error::TooSmall)
+    ));
+}
+
+#[test]
+fn recovers_what_was_left_unread() {
+    let mut memory = Memory([0; 512]);
+
+    {
+        let (ring, _) = Ring::from_persistent(&mut memory.0).unwrap();
+        let (producer, consumer) = ring.split_framed().unwrap();
+
+        for record in [&b"sent"[..], b"unsent", b"also unsent"] {
+            push(&producer, record);
+        }
+
+        consumer.read().unwrap().commit();
+
+        // a grant in progress at the time of the reset is lost
+        let mut grant = producer.grant(4).unwrap();
+        grant.buf_mut().fill(0xee);
+        mem::forget(grant);
+    }
+
+    let (ring, boot) = Ring::from_persistent(&mut memory.0).unwrap();
+    let (producer, consumer) = ring.split_framed().unwrap();
+
+    // the records and their one byte headers
+    assert_eq!(
+        boot,
+        Boot::Warm {
+            pending: 6 + 11 + 2
+        }
+    );
+    assert_eq!(drain(&consumer), [&b"unsent"[..], b"also unsent"]);
+
+    // from then on it's a regular ring
+    push(&producer, b"new");
+    assert_eq!(
+        recover(&mut memory.0),
+        (Boot::Warm { pending: 4 }, vec![b"new".to_vec()])
+    );
+}
+
+#[test]
+fn recovers_a_wrapped_ring() {
+    let mut memory = Memory([0; 512]);
+    let mut expected = VecDeque::new();
+
+    {
+        let (ring, _) = Ring::from_persistent(&mut memory.0).unwrap();
+        let (producer, consumer) = ring.split_framed().unwrap();
+
+        // keep going until the unread records straddle the end of the ring
+        for i in 0u8..=u8::MAX {
+            let record = vec![i; 1 + i as usize % 23];
+
+            while producer.grant(record.len()).is_err() {
+                consumer.read().unwrap().commit();
+                expected.pop_front();
+            }
+
+            push(&producer, &record);
+            expected.push_back(record);
+        }
+    }
+
+    let pending = expected.iter().map(|record| 1 + record.len()).sum();
+    let (boot, records) = recover(&mut memory.0);
+
+    assert_eq!(boot, Boot::Warm { pending });
+    assert_eq!(records, Vec::from(expected));
+}
+
+#[test]
+fn starts_cold_when_the_seal_does_not_match() {
+    let mut memory = Memory([0; 512]);
+
+    let write = |memory: &mut [u8]| {
+        let (ring, _) = Ring::from_persistent(memory).unwrap();
+        let (producer, _) = ring.split_framed().unwrap();
+        push(&producer, b"lost");
+    };
+
+    // a different capacity is a different ring
+    write(&mut memory.0);
+    assert_eq!(recover(&mut memory.0[..400]), (Boot::Cold, vec![]));
+
+    // and so is anything with a corrupted seal
+    write(&mut memory.0);
+
+    for byte in 0..(5 * mem::size_of::<usize>()) {
+        let mut corrupted = Memory(memory.0);
+        corrupted.0[byte] ^= 0x10;
+        assert_eq!(recover(&mut corrupted.0), (Boot::Cold, vec![]));
+    }
+
+    assert_eq!(
+        recover(&mut memory.0),
+        (Boot::Warm { pending: 5 }, vec![b"lost".to_vec()])
+    );
+}
+
+#[test]
+fn drops_records_that_did_not_survive() {
+    let mut memory = Memory([0; 512]);
+
+    {
+        let (ring, _) = Ring::from_persistent(&mut memory.0).unwrap();
+        let (producer, _) = ring.split_framed().unwrap();
+
+        for record in [&b"intact"[..], b"mangled", b"after it"] {
+            push(&producer, record);
+        }
+    }
+
+    // a header claiming more than there is
+    let at = memory.0.windows(7).position(|w| w == b"mangled").unwrap();
+    memory.0[at - 1] = 0x7f;
+
+    let (ring, boot) = Ring::from_persistent(&mut memory.0).unwrap();
+    let (producer, consumer) = ring.split_framed().unwrap();
+    assert_eq!(boot, Boot::Warm { pending: 7 + 8 + 9 });
+
+    assert_eq!(consumer.read().unwrap().buf(), b"intact");
+    consumer.read().unwrap().commit();
+    assert!(matches!(consumer.read(), Err(Error::Codec)));
+    assert_eq!(
+        consumer.dropped(),
+        Dropped {
+            records: 1,
+            bytes: 8 + 9
+        }
+    );
+
+    // and the ring carries on from there
+    push(&producer, b"new");
+    assert_eq!(drain(&consumer), [b"new"]);
+}
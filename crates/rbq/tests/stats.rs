@@ -0,0 +1,78 @@
+// The `stats` counters, checked against what a short sequence of grants should leave behind.
+
+#![cfg(feature = "stats")]
+
+use rbq::{Buffer, Error, Ring};
+
+#[test]
+fn counts_both_ends() {
+    let buffer = Buffer::<16>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split().unwrap();
+
+    producer.grant_exact(10).unwrap().commit(8);
+    let stats = ring.stats();
+    assert_eq!(
+        (stats.used, stats.high_water, stats.written, stats.read),
+        (8, 8, 8, 0)
+    );
+
+    let grant = consumer.read().unwrap();
+    assert_eq!(consumer.read().err(), Some(Error::GrantInProgress));
+    grant.commit(5);
+    assert_eq!(producer.grant_exact(20).err(), Some(Error::TooLarge));
+
+    // after this one writes wrap, and 5 elements from the start would run into the unread data
+    producer.grant_exact(6).unwrap().commit(6);
+    assert_eq!(producer.grant_exact(5).err(), Some(Error::InsufficientSize));
+    producer.grant_exact(3).unwrap().commit(3);
+
+    let stats = ring.stats();
+    assert_eq!(
+        (stats.used, stats.high_water, stats.written, stats.read),
+        (12, 12, 17, 5)
+    );
+    assert_eq!(
+        (
+            stats.full,
+            stats.empty,
+            stats.too_large,
+            stats.grant_in_progress
+        ),
+        (1, 0, 1, 1)
+    );
+
+    consumer.read().unwrap().commit(9);
+    consumer.read().unwrap().commit(3);
+    assert_eq!(consumer.read().err(), Some(Error::InsufficientSize));
+
+    let stats = ring.stats();
+    assert_eq!((stats.used, stats.read), (0, 17));
+    assert_eq!((stats.full, stats.empty), (1, 1));
+}
+
+#[test]
+fn overwriting_counts_once() {
+    let buffer = Buffer::<16>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split_framed().unwrap();
+
+    for record in [b"aaaa", b"bbbb", b"cccc", b"dddd"] {
+        let mut grant = producer.grant_overwrite(4).unwrap();
+        grant.buf_mut().copy_from_slice(record);
+        grant.commit(4);
+    }
+
+    // the two records pushed out count as read, and making room isn't a failure
+    let stats = ring.stats();
+    assert_eq!((stats.written, stats.read, stats.used), (20, 10, 10));
+    assert_eq!(stats.full, 0);
+
+    // but giving up is, once
+    let held = consumer.read().unwrap();
+    assert!(producer.grant_overwrite(8).is_err());
+    held.commit();
+
+    let stats = ring.stats();
+    assert_eq!((stats.full, stats.read), (1, 15));
+}
@@ -0,0 +1,77 @@
+// Read grants as a `Stream`, polled by hand with a waker that counts its wakeups.
+
+#![cfg(feature = "stream")]
+
+use std::pin::pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll, Wake, Waker};
+
+use futures_core::Stream;
+use rbq::{Buffer, Error, Ring};
+
+#[derive(Default)]
+struct Count(AtomicUsize);
+
+impl Wake for Count {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn yields_grants_as_they_are_committed() {
+    let buffer = Buffer::<16>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split().unwrap();
+    let count = Arc::new(Count::default());
+    let waker = Waker::from(count.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    let mut stream = pin!(consumer.stream());
+    assert!(stream.as_mut().poll_next(&mut cx).is_pending());
+
+    producer.grant_exact(3).unwrap().commit(3);
+    assert_eq!(count.0.load(Ordering::Relaxed), 1);
+
+    let Poll::Ready(Some(Ok(grant))) = stream.as_mut().poll_next(&mut cx) else {
+        panic!("no grant after a commit");
+    };
+    assert_eq!(grant.buf().len(), 3);
+
+    // the previous item has to go first
+    assert!(matches!(
+        stream.as_mut().poll_next(&mut cx),
+        Poll::Ready(Some(Err(Error::GrantInProgress)))
+    ));
+    grant.commit(2);
+
+    let Poll::Ready(Some(Ok(grant))) = stream.as_mut().poll_next(&mut cx) else {
+        panic!("the rest of the data is gone");
+    };
+    assert_eq!(grant.buf().len(), 1);
+}
+
+#[test]
+fn dropping_a_pending_stream_loses_nothing() {
+    let buffer = Buffer::<16>::new();
+    let ring = Ring::new(&buffer);
+    let (producer, consumer) = ring.split_framed().unwrap();
+    let waker = Waker::from(Arc::new(Count::default()));
+    let mut cx = Context::from_waker(&waker);
+
+    {
+        let mut stream = pin!(consumer.stream());
+        assert!(stream.as_mut().poll_next(&mut cx).is_pending());
+    }
+
+    let mut grant = producer.grant(4).unwrap();
+    grant.buf_mut().copy_from_slice(b"ping");
+    grant.commit(4);
+
+    let mut stream = pin!(consumer.stream());
+    let Poll::Ready(Some(Ok(record))) = stream.as_mut().poll_next(&mut cx) else {
+        panic!("record went missing");
+    };
+    assert_eq!(record.buf(), b"ping");
+}
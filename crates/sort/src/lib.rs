@@ -6,8 +6,9 @@ use core::{mem, ptr};
 /// Sort `v` **without** preserving initial order of equal elements.
 ///
 /// - Guaranteed O(N * log(N)) worst case perf
-/// - No adaptiveness
+/// - Adaptive to already sorted or reversed input and to many equal elements
 /// - Branch miss-prediction not affected by outcome of comparison function
+/// - No allocation, recursion depth bounded by O(log(N))
 ///
 /// If `T: Ord` does not implement a total order the resulting order is
 /// unspecified. All original elements will remain in `v` and any possible modifications via
@@ -52,9 +53,270 @@ fn unstable_sort<T, F: FnMut(&T, &T) -> bool>(v: &mut [T], mut is_less: F) {
         return;
     }
 
-    // SAFETY: We just checked that len >= 2.
-    unsafe {
-        heapsort(v, &mut is_less);
+    // Find the initial run, if it spans the whole slice the input is already sorted or strictly
+    // descending and a reversal is all that's left to do.
+    let (run_len, was_reversed) = find_existing_run(v, &mut is_less);
+
+    if run_len == len {
+        if was_reversed {
+            v.reverse();
+        }
+
+        return;
+    }
+
+    // Limit the number of imbalanced partitions to `2 * floor(log2(len))`, past that `recurse`
+    // switches to heapsort.
+    let limit = 2 * (len | 1).ilog2();
+    recurse(v, &mut is_less, None, limit);
+}
+
+/// Slices of up to this length get sorted using insertion sort.
+const SMALL_SORT_THRESHOLD: usize = 20;
+
+/// Slices of at least this length pick their pivot from a recursive median of medians.
+const PSEUDO_MEDIAN_REC_THRESHOLD: usize = 64;
+
+/// Returns the length of the run at the start of `v`, and whether it is strictly descending.
+fn find_existing_run<T, F>(v: &[T], is_less: &mut F) -> (usize, bool)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let len = v.len();
+
+    if len < 2 {
+        return (len, false);
+    }
+
+    let strictly_descending = is_less(&v[1], &v[0]);
+    let mut run_len = 2;
+
+    if strictly_descending {
+        while run_len < len && is_less(&v[run_len], &v[run_len - 1]) {
+            run_len += 1;
+        }
+    } else {
+        while run_len < len && !is_less(&v[run_len], &v[run_len - 1]) {
+            run_len += 1;
+        }
+    }
+
+    (run_len, strictly_descending)
+}
+
+/// Sorts `v` recursively with a pattern-defeating quicksort.
+///
+/// `ancestor_pivot` is the pivot the closest ancestor partition split on, if `v` lies to the
+/// right of it. Every element of `v` is then `>=` it, so if the new pivot is equal to it the
+/// partition only has to separate elements equal to the pivot from larger ones, which makes
+/// slices with many duplicates cheap.
+///
+/// `limit` is the number of allowed imbalanced partitions before switching to `heapsort`.
+fn recurse<'a, T, F>(
+    mut v: &'a mut [T],
+    is_less: &mut F,
+    mut ancestor_pivot: Option<&'a T>,
+    mut limit: u32,
+) where
+    F: FnMut(&T, &T) -> bool,
+{
+    loop {
+        let len = v.len();
+
+        if len <= SMALL_SORT_THRESHOLD {
+            insertion_sort(v, is_less);
+            return;
+        }
+
+        // Too many imbalanced partitions, fall back to heapsort to guarantee O(N * log(N)).
+        if limit == 0 {
+            // SAFETY: We just checked that len > SMALL_SORT_THRESHOLD >= 2.
+            unsafe {
+                heapsort(v, is_less);
+            }
+            return;
+        }
+
+        let pivot_pos = choose_pivot(v, is_less);
+
+        // The pivot is equal to the ancestor pivot and so the smallest element in `v`. Put all
+        // elements equal to it to the left and only continue with the larger ones.
+        if let Some(ancestor_pivot) = ancestor_pivot {
+            if !is_less(ancestor_pivot, &v[pivot_pos]) {
+                let num_le = partition(v, pivot_pos, &mut |a, b| !is_less(b, a));
+                v = &mut v[(num_le + 1)..];
+                continue;
+            }
+        }
+
+        let num_lt = partition(v, pivot_pos, is_less);
+
+        // A badly unbalanced partition hints at a pattern the pivot selection keeps falling for.
+        // Shuffle a few elements around on both sides to break it up.
+        if num_lt < len / 8 || len - num_lt - 1 < len / 8 {
+            limit -= 1;
+            break_patterns(&mut v[..num_lt]);
+            break_patterns(&mut v[(num_lt + 1)..]);
+        }
+
+        let (left, right) = v.split_at_mut(num_lt);
+        let (pivot, right) = right.split_at_mut(1);
+        let pivot = &pivot[0];
+
+        // Recurse into the left side, iterate on the right one.
+        recurse(left, is_less, ancestor_pivot, limit);
+        ancestor_pivot = Some(pivot);
+        v = right;
+    }
+}
+
+/// Sorts `v` by inserting every element into the sorted prefix before it. Only used on short
+/// slices, so it sticks to plain swaps, which also keeps every element in `v` should `is_less`
+/// panic.
+fn insertion_sort<T, F>(v: &mut [T], is_less: &mut F)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    for i in 1..v.len() {
+        let mut j = i;
+
+        while j > 0 && is_less(&v[j], &v[j - 1]) {
+            v.swap(j, j - 1);
+            j -= 1;
+        }
+    }
+}
+
+/// Picks a pivot from three samples spread across `v`, or from a recursive median of medians for
+/// longer slices. Returns its index.
+fn choose_pivot<T, F>(v: &[T], is_less: &mut F) -> usize
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let len = v.len();
+    let len_div_8 = len / 8;
+
+    let a = 0;
+    let b = len_div_8 * 4;
+    let c = len_div_8 * 7;
+
+    if len < PSEUDO_MEDIAN_REC_THRESHOLD {
+        median3(v, a, b, c, is_less)
+    } else {
+        median3_rec(v, a, b, c, len_div_8, is_less)
+    }
+}
+
+/// Calculates an approximate median of 3 elements from sections a, b, c, or recursively from an
+/// approximation of each, if they're large enough. By dividing the size of each section by 8
+/// when recursing we have logarithmic recursion depth and overall sample from
+/// f(n) = 3*f(n/8) -> f(n) = O(n^(log(3)/log(8))) ~= O(n^0.528) elements.
+fn median3_rec<T, F>(
+    v: &[T],
+    mut a: usize,
+    mut b: usize,
+    mut c: usize,
+    n: usize,
+    is_less: &mut F,
+) -> usize
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    if n * 8 >= PSEUDO_MEDIAN_REC_THRESHOLD {
+        let n8 = n / 8;
+        a = median3_rec(v, a, a + n8 * 4, a + n8 * 7, n8, is_less);
+        b = median3_rec(v, b, b + n8 * 4, b + n8 * 7, n8, is_less);
+        c = median3_rec(v, c, c + n8 * 4, c + n8 * 7, n8, is_less);
+    }
+
+    median3(v, a, b, c, is_less)
+}
+
+/// Returns the index of the median of `v[a]`, `v[b]` and `v[c]`.
+fn median3<T, F>(v: &[T], a: usize, b: usize, c: usize, is_less: &mut F) -> usize
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let x = is_less(&v[a], &v[b]);
+    let y = is_less(&v[a], &v[c]);
+
+    if x == y {
+        // If x=y=0 then b,c <= a. In this case we want to return max(b,c).
+        // If x=y=1 then a < b,c. In this case we want to return min(b,c).
+        // By toggling the outcome of b < c using XOR x we get this behavior.
+        let z = is_less(&v[b], &v[c]);
+        if z ^ x { c } else { b }
+    } else {
+        // Either c <= a < b or b <= a < c, thus a is our median.
+        a
+    }
+}
+
+/// Partitions `v` around `v[pivot_pos]`, so that every element for which `is_less(elem, pivot)`
+/// holds comes before the pivot and every other one after it. Returns the number of elements
+/// before the pivot, which is where the pivot ends up.
+fn partition<T, F>(v: &mut [T], pivot_pos: usize, is_less: &mut F) -> usize
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    // Move the pivot out of the way to the front, and partition the rest against it.
+    v.swap(0, pivot_pos);
+    let (pivot, rest) = v.split_at_mut(1);
+    let num_lt = partition_lomuto_branchless(rest, &pivot[0], is_less);
+
+    // Put the pivot between the two partitions.
+    v.swap(0, num_lt);
+    num_lt
+}
+
+/// Lomuto partition without a branch on the comparison result: every element is swapped to the
+/// boundary and the boundary only advances if it belongs to the left side. Only ever swapping
+/// keeps every element in `v` should `is_less` panic.
+#[inline(never)]
+fn partition_lomuto_branchless<T, F>(v: &mut [T], pivot: &T, is_less: &mut F) -> usize
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let mut left = 0;
+
+    for right in 0..v.len() {
+        let right_is_lt = is_less(&v[right], pivot);
+        v.swap(left, right);
+        left += right_is_lt as usize;
+    }
+
+    left
+}
+
+/// Swaps a few elements in the middle of `v` with pseudo-randomly picked ones, which takes apart
+/// the patterns that lead to unbalanced partitions, like organ-pipe or sawtooth inputs.
+fn break_patterns<T>(v: &mut [T]) {
+    let len = v.len();
+
+    if len < 8 {
+        return;
+    }
+
+    // xorshift32, seeded with the length so the result is deterministic
+    let mut random = len as u32 | 1;
+    let mut gen_u32 = || {
+        random ^= random << 13;
+        random ^= random >> 17;
+        random ^= random << 5;
+        random
+    };
+
+    let modulus = len.next_power_of_two();
+    let pos = len / 4 * 2;
+
+    for i in 0..3 {
+        // `len <= modulus < 2 * len`, so subtracting `len` once is enough to bring it in range
+        let mut other = gen_u32() as usize & (modulus - 1);
+        if other >= len {
+            other -= len;
+        }
+
+        v.swap(pos - 1 + i, other);
     }
 }
 
@@ -81,13 +343,19 @@ where
 
     // Build the heap in linear time.
     for i in (0..len / 2).rev() {
-        sift_down(v, i, is_less);
+        // SAFETY: i < len / 2 < len.
+        unsafe {
+            sift_down(v, i, is_less);
+        }
     }
 
     // Pop maximal elements from the heap.
     for i in (1..len).rev() {
         v.swap(0, i);
-        sift_down(&mut v[..i], 0, is_less);
+        // SAFETY: i >= 1, so the heap is never empty.
+        unsafe {
+            sift_down(&mut v[..i], 0, is_less);
+        }
     }
 }
 